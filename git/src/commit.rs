@@ -0,0 +1,210 @@
+use alloc::vec::Vec;
+use alloc::format;
+use failure::Fail;
+use gulp::{Parse, ParseResult};
+use crate::{Object, ObjectId, ObjectKind, Signature};
+use crate::fields::{Fields, FieldsParser, parse_id, encode_field, encode_message};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Commit {
+    pub tree: ObjectId,
+    pub parents: Vec<ObjectId>,
+    pub author: Signature,
+    pub committer: Signature,
+    // everything after the committer, in order: encoding, mergetag, gpgsig, and anything unknown
+    pub extra: Vec<(Vec<u8>, Vec<u8>)>,
+    // None when the headers aren't followed by a blank line at all
+    pub message: Option<Vec<u8>>
+}
+
+impl Commit {
+    pub fn encoding(&self) -> Option<&[u8]> {
+        self.extra_field(b"encoding").next()
+    }
+    pub fn gpgsig(&self) -> Option<&[u8]> {
+        self.extra_field(b"gpgsig").next()
+    }
+    pub fn mergetags(&self) -> impl Iterator<Item=&[u8]> {
+        self.extra_field(b"mergetag")
+    }
+    fn extra_field<'a>(&'a self, key: &'a [u8]) -> impl Iterator<Item=&'a [u8]> {
+        self.extra.iter().filter(move |(k, _)| k == key).map(|(_, v)| &v[..])
+    }
+}
+
+impl Object for Commit {
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Commit
+    }
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_field(&mut out, b"tree", format!("{}", self.tree).as_bytes());
+        for parent in &self.parents {
+            encode_field(&mut out, b"parent", format!("{}", parent).as_bytes());
+        }
+        let mut signature = Vec::new();
+        self.author.encode(&mut signature);
+        encode_field(&mut out, b"author", &signature);
+        signature.clear();
+        self.committer.encode(&mut signature);
+        encode_field(&mut out, b"committer", &signature);
+        for (key, value) in &self.extra {
+            encode_field(&mut out, key, value);
+        }
+        encode_message(&mut out, self.message.as_deref());
+        out
+    }
+}
+
+#[derive(Copy, Clone, Debug, Fail, Eq, PartialEq)]
+pub enum InvalidCommit {
+    #[fail(display = "malformed commit header line")]
+    Header,
+    #[fail(display = "missing or invalid commit tree")]
+    Tree,
+    #[fail(display = "invalid commit parent")]
+    Parent,
+    #[fail(display = "missing or invalid commit author")]
+    Author,
+    #[fail(display = "missing or invalid commit committer")]
+    Committer
+}
+
+#[derive(Default, Debug, Eq, PartialEq)]
+struct CommitFields {
+    tree: Option<ObjectId>,
+    parents: Vec<ObjectId>,
+    author: Option<Signature>,
+    committer: Option<Signature>,
+    extra: Vec<(Vec<u8>, Vec<u8>)>
+}
+
+impl Fields for CommitFields {
+    type Output = Commit;
+    type Err = InvalidCommit;
+    fn malformed() -> InvalidCommit {
+        InvalidCommit::Header
+    }
+    fn field(&mut self, key: &[u8], value: Vec<u8>) -> Result<(), InvalidCommit> {
+        if self.tree.is_none() {
+            if key != b"tree" {
+                return Err(InvalidCommit::Tree);
+            }
+            self.tree = Some(parse_id(&value).ok_or(InvalidCommit::Tree)?);
+        } else if self.author.is_none() {
+            match key {
+                b"parent" => self.parents.push(parse_id(&value).ok_or(InvalidCommit::Parent)?),
+                b"author" => self.author = Some(Signature::parse(&value).ok_or(InvalidCommit::Author)?),
+                _ => return Err(InvalidCommit::Author)
+            }
+        } else if self.committer.is_none() {
+            if key != b"committer" {
+                return Err(InvalidCommit::Committer);
+            }
+            self.committer = Some(Signature::parse(&value).ok_or(InvalidCommit::Committer)?);
+        } else {
+            self.extra.push((key.to_vec(), value));
+        }
+        Ok(())
+    }
+    fn finish(self, message: Option<Vec<u8>>) -> Result<Commit, InvalidCommit> {
+        Ok(Commit {
+            tree: self.tree.ok_or(InvalidCommit::Tree)?,
+            parents: self.parents,
+            author: self.author.ok_or(InvalidCommit::Author)?,
+            committer: self.committer.ok_or(InvalidCommit::Committer)?,
+            extra: self.extra,
+            message
+        })
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct CommitParser(FieldsParser<CommitFields>);
+
+impl CommitParser {
+    pub fn new(size: u64) -> CommitParser {
+        CommitParser(FieldsParser::new(CommitFields::default(), size))
+    }
+}
+
+impl Parse for CommitParser {
+    type Output = Commit;
+    type Err = InvalidCommit;
+    fn parse(self, buf: &[u8]) -> ParseResult<Self> {
+        match self.0.parse(buf) {
+            gulp::Result::Incomplete(p) => gulp::Result::Incomplete(CommitParser(p)),
+            gulp::Result::Err(e) => gulp::Result::Err(e),
+            gulp::Result::Ok(commit, tail) => gulp::Result::Ok(commit, tail)
+        }
+    }
+}
+
+#[test]
+fn parse_commit() {
+    let body: &[u8] = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+        parent 0123456789abcdef0123456789abcdef01234567\n\
+        parent 89abcdef0123456789abcdef0123456789abcdef\n\
+        author A U Thor <author@example.com> 1112911993 -0700\n\
+        committer C O Mitter <> 1112912053 +0530\n\
+        encoding ISO-8859-1\n\
+        gpgsig -----BEGIN PGP SIGNATURE-----\n \n wsBcBAABCAAQ\n -----END PGP SIGNATURE-----\n\
+        \n\
+        subject\n\nbody\n";
+
+    let whole = match CommitParser::new(body.len() as u64).parse(body) {
+        gulp::Result::Ok(commit, tail) => {
+            assert!(tail.is_empty());
+            commit
+        }
+        r => panic!("{:?}", r)
+    };
+    let mut parser = CommitParser::new(body.len() as u64);
+    for (i, b) in body.iter().enumerate() {
+        parser = match parser.parse(core::slice::from_ref(b)) {
+            gulp::Result::Incomplete(p) => p,
+            gulp::Result::Ok(commit, _) if i == body.len() - 1 => {
+                assert_eq!(commit, whole);
+                break;
+            }
+            r => panic!("{:?}", r)
+        };
+    }
+
+    assert_eq!(whole.tree, ObjectId::from_hex(b"4b825dc642cb6eb9a060e54bf8d69288fbee4904").unwrap());
+    assert_eq!(whole.parents.len(), 2);
+    assert_eq!(whole.author.name, b"A U Thor");
+    assert_eq!(whole.author.time, crate::Time { seconds: 1112911993, offset: -420 });
+    assert_eq!(whole.committer.email, b"");
+    assert_eq!(whole.committer.time.offset, 330);
+    assert_eq!(whole.encoding(), Some(&b"ISO-8859-1"[..]));
+    assert_eq!(whole.gpgsig(), Some(&b"-----BEGIN PGP SIGNATURE-----\n\nwsBcBAABCAAQ\n-----END PGP SIGNATURE-----"[..]));
+    assert_eq!(whole.mergetags().count(), 0);
+    assert_eq!(whole.message, Some(b"subject\n\nbody\n".to_vec()));
+    assert_eq!(whole.encode(), body);
+
+    let parse = |body: &[u8]| match CommitParser::new(body.len() as u64).parse(body) {
+        gulp::Result::Ok(commit, _) => Ok(commit),
+        gulp::Result::Err(e) => Err(e),
+        gulp::Result::Incomplete(_) => unreachable!()
+    };
+    let minimal = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\nauthor  <> 0 +0000\ncommitter  <> 0 +0000\n";
+    assert_eq!(parse(minimal).unwrap().message, None);
+    assert_eq!(parse(minimal).unwrap().encode(), &minimal[..]);
+    assert_eq!(format!("{}", parse(minimal).unwrap().id().unwrap()), "58e3511914451b8973688e928d06173a2ab00584");
+    assert_eq!(parse(&minimal[..minimal.len() - 1]), Err(InvalidCommit::Header));
+    assert_eq!(parse(&minimal[46..]), Err(InvalidCommit::Tree));
+    assert_eq!(parse(b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\ncommitter <> 0 +0000\n"), Err(InvalidCommit::Author));
+    assert_eq!(parse(b"tree 4B825DC642CB6EB9A060E54BF8D69288FBEE4904\nauthor  <> 0 +0000\ncommitter  <> 0 +0000\n"), Err(InvalidCommit::Tree));
+
+    // odd signatures from old commits are kept as they were written, so the id stays the same
+    let odd: &[u8] = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+        author A U Thor<author@example.com> 1112911993 -0000\n\
+        committer C O Mitter <committer@example.com> 1112912053 -0000\n\
+        \n\
+        odd\n";
+    let commit = parse(odd).unwrap();
+    assert_eq!(commit.committer.time, crate::Time { seconds: 1112912053, offset: 0 });
+    assert_eq!(commit.encode(), odd);
+    assert_eq!(format!("{}", commit.id().unwrap()), "5ae9c97abe3b6b62e3b1e7edc4d0af5c5ab32fd7");
+}
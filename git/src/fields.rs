@@ -0,0 +1,134 @@
+use alloc::vec::Vec;
+use core::{cmp, mem};
+use failure::Fail;
+use gulp::{Parse, ParseResult};
+use crate::ObjectId;
+
+// the "key value\n" header lines shared by commits and tags, followed by a blank line and a message.
+// a line starting with a space continues the previous value, as used for signatures and mergetags.
+pub(crate) trait Fields {
+    type Output;
+    type Err: Fail;
+    fn malformed() -> Self::Err;
+    fn field(&mut self, key: &[u8], value: Vec<u8>) -> Result<(), Self::Err>;
+    fn finish(self, message: Option<Vec<u8>>) -> Result<Self::Output, Self::Err>;
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct FieldsParser<F> {
+    fields: F,
+    remaining: u64,
+    line: Vec<u8>,
+    pending: Option<(Vec<u8>, Vec<u8>)>,
+    message: Option<Vec<u8>>
+}
+
+impl<F: Fields> FieldsParser<F> {
+    pub(crate) fn new(fields: F, len: u64) -> FieldsParser<F> {
+        FieldsParser { fields, remaining: len, line: Vec::new(), pending: None, message: None }
+    }
+    fn line(&mut self, line: Vec<u8>) -> Result<(), F::Err> {
+        if line.first() == Some(&b' ') {
+            return match self.pending {
+                Some((_, ref mut value)) => {
+                    value.push(b'\n');
+                    value.extend_from_slice(&line[1..]);
+                    Ok(())
+                }
+                None => Err(F::malformed())
+            };
+        }
+        self.flush()?;
+        if line.is_empty() {
+            self.message = Some(Vec::new());
+            return Ok(());
+        }
+        match line.iter().position(|&b| b == b' ') {
+            Some(i) => {
+                let value = line[i + 1..].to_vec();
+                let mut key = line;
+                key.truncate(i);
+                self.pending = Some((key, value));
+                Ok(())
+            }
+            None => Err(F::malformed())
+        }
+    }
+    fn flush(&mut self) -> Result<(), F::Err> {
+        match self.pending.take() {
+            Some((key, value)) => self.fields.field(&key, value),
+            None => Ok(())
+        }
+    }
+    fn finish(mut self) -> Result<F::Output, F::Err> {
+        if !self.line.is_empty() {
+            return Err(F::malformed());
+        }
+        self.flush()?;
+        self.fields.finish(self.message)
+    }
+}
+
+impl<F: Fields> Parse for FieldsParser<F> {
+    type Output = F::Output;
+    type Err = F::Err;
+    fn parse(mut self, buf: &[u8]) -> ParseResult<Self> {
+        let n = cmp::min(buf.len() as u64, self.remaining) as usize;
+        let (mut buf, tail) = buf.split_at(n);
+        self.remaining -= n as u64;
+        while !buf.is_empty() {
+            if let Some(ref mut message) = self.message {
+                message.extend_from_slice(buf);
+                break;
+            }
+            match buf.iter().position(|&b| b == b'\n') {
+                None => {
+                    self.line.extend_from_slice(buf);
+                    break;
+                }
+                Some(i) => {
+                    self.line.extend_from_slice(&buf[..i]);
+                    buf = &buf[i + 1..];
+                    let line = mem::take(&mut self.line);
+                    if let Err(e) = self.line(line) {
+                        return gulp::Result::Err(e);
+                    }
+                }
+            }
+        }
+        if self.remaining != 0 {
+            return gulp::Result::Incomplete(self);
+        }
+        match self.finish() {
+            Ok(v) => gulp::Result::Ok(v, tail),
+            Err(e) => gulp::Result::Err(e)
+        }
+    }
+}
+
+// ids in headers are written in lowercase, and an uppercase one wouldn't be written back the same
+pub(crate) fn parse_id(hex: &[u8]) -> Option<ObjectId> {
+    if hex.iter().any(u8::is_ascii_uppercase) {
+        return None;
+    }
+    ObjectId::from_hex(hex).ok()
+}
+
+pub(crate) fn encode_field(out: &mut Vec<u8>, key: &[u8], value: &[u8]) {
+    out.extend_from_slice(key);
+    out.push(b' ');
+    for (i, line) in value.split(|&b| b == b'\n').enumerate() {
+        if i != 0 {
+            out.extend_from_slice(b"\n ");
+        }
+        out.extend_from_slice(line);
+    }
+    out.push(b'\n');
+}
+
+pub(crate) fn encode_message(out: &mut Vec<u8>, message: Option<&[u8]>) {
+    if let Some(message) = message {
+        out.push(b'\n');
+        out.extend_from_slice(message);
+    }
+}
@@ -0,0 +1,76 @@
+use core::fmt;
+use failure::Fail;
+use sha2::Digest;
+use crate::ObjectId;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum HashKind {
+    Sha1,
+    Sha256
+}
+
+impl HashKind {
+    pub fn digest_len(&self) -> usize {
+        match *self {
+            HashKind::Sha1   => 20,
+            HashKind::Sha256 => 32
+        }
+    }
+    // as spelled in a repository's extensions.objectFormat
+    pub fn name(&self) -> &'static str {
+        match *self {
+            HashKind::Sha1   => "sha1",
+            HashKind::Sha256 => "sha256"
+        }
+    }
+    pub fn from_name(name: &[u8]) -> Option<HashKind> {
+        match name {
+            b"sha1"   => Some(HashKind::Sha1),
+            b"sha256" => Some(HashKind::Sha256),
+            _         => None
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Fail, Eq, PartialEq)]
+#[fail(display = "SHA-1 collision attack detected")]
+pub struct Collision(());
+
+pub enum Hasher {
+    Sha1(sha1dc::Hasher),
+    Sha256(sha2::Sha256)
+}
+
+impl Hasher {
+    pub fn new(hash: HashKind) -> Hasher {
+        match hash {
+            HashKind::Sha1   => Hasher::Sha1(sha1dc::Hasher::new()),
+            HashKind::Sha256 => Hasher::Sha256(sha2::Sha256::new())
+        }
+    }
+    pub fn hash(&self) -> HashKind {
+        match *self {
+            Hasher::Sha1(_)   => HashKind::Sha1,
+            Hasher::Sha256(_) => HashKind::Sha256
+        }
+    }
+    pub fn update(&mut self, buffer: &[u8]) {
+        match *self {
+            Hasher::Sha1(ref mut h)   => h.update(buffer),
+            Hasher::Sha256(ref mut h) => Digest::update(h, buffer)
+        }
+    }
+    pub fn digest(self) -> Result<ObjectId, Collision> {
+        match self {
+            Hasher::Sha1(h)   => h.digest().map(ObjectId::Sha1).map_err(|_| Collision(())),
+            Hasher::Sha256(h) => Ok(ObjectId::Sha256(h.finalize().into()))
+        }
+    }
+}
+
+impl fmt::Write for Hasher {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.update(s.as_bytes());
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use std::io;
-use crate::{ObjectHasher, ObjectId};
+use failure::Fail;
+use crate::{Collision, ObjectHasher, ObjectHeader, ObjectId, Object};
 
 impl io::Write for ObjectHasher {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
@@ -17,7 +18,7 @@ pub struct ObjectWriter<W: io::Write> {
 }
 
 impl<W: io::Write> ObjectWriter<W> {
-    pub fn digest(self) -> ObjectId {
+    pub fn digest(self) -> Result<ObjectId, Collision> {
         self.hasher.digest()
     }
 }
@@ -32,3 +33,17 @@ impl<W: io::Write> io::Write for ObjectWriter<W> {
         self.writer.flush()
     }
 }
+
+pub fn write_object<W: io::Write, O: Object>(writer: W, object: &O) -> io::Result<ObjectId> {
+    let body = object.encode();
+    let hasher = ObjectHasher::new(ObjectHeader { kind: object.kind(), size: body.len() as u64 });
+    let mut writer = ObjectWriter { hasher, writer };
+    io::Write::write_all(&mut writer, &body)?;
+    Ok(writer.digest()?)
+}
+
+impl From<Collision> for io::Error {
+    fn from(e: Collision) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, e.compat())
+    }
+}
@@ -1,11 +1,32 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
+extern crate alloc;
+
 use void::Void;
 use gulp::{Parse, ParseResult};
 use core::fmt::{self, Write};
+use core::str::FromStr;
+use failure::Fail;
+use alloc::vec::Vec;
 
+pub use hash::*;
+pub use commit::*;
+pub use signature::*;
+pub use tree::*;
+pub use tag::*;
+pub use prefix::*;
 #[cfg(feature = "std")] pub use io::*;
+#[cfg(feature = "std")] pub use loose::*;
+
+mod hash;
+mod fields;
+mod commit;
+mod signature;
+mod tree;
+mod tag;
+mod prefix;
 #[cfg(feature = "std")] mod io;
+#[cfg(feature = "std")] mod loose;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum ObjectKind {
@@ -24,6 +45,15 @@ impl ObjectKind {
             ObjectKind::Tag    => "tag"
         }
     }
+    pub fn from_name(name: &[u8]) -> Option<ObjectKind> {
+        match name {
+            b"commit" => Some(ObjectKind::Commit),
+            b"tree"   => Some(ObjectKind::Tree),
+            b"blob"   => Some(ObjectKind::Blob),
+            b"tag"    => Some(ObjectKind::Tag),
+            _         => None
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -32,28 +62,221 @@ pub struct ObjectHeader {
     pub size: u64
 }
 
+#[derive(Copy, Clone, Debug, Fail, Eq, PartialEq)]
+pub enum InvalidObjectHeader {
+    #[fail(display = "unknown object kind")]
+    Kind,
+    #[fail(display = "invalid object size")]
+    Size
+}
+
+// the "<kind> <size>\0" header that loose objects start with, and that object ids hash over
+#[derive(Debug, Eq, PartialEq)]
+pub struct ObjectHeaderParser(ObjectHeaderParserState);
+
+#[derive(Debug, Eq, PartialEq)]
+enum ObjectHeaderParserState {
+    Kind([u8; 6], u8),
+    Size(ObjectKind, Option<u64>)
+}
+
+impl Default for ObjectHeaderParser {
+    fn default() -> ObjectHeaderParser {
+        ObjectHeaderParser(ObjectHeaderParserState::Kind([0; 6], 0))
+    }
+}
+
+impl Parse for ObjectHeaderParser {
+    type Output = ObjectHeader;
+    type Err = InvalidObjectHeader;
+    fn parse(self, buf: &[u8]) -> ParseResult<Self> {
+        match self.0 {
+            ObjectHeaderParserState::Kind(name, n) => ObjectHeaderParser::parse_kind(name, n, buf),
+            ObjectHeaderParserState::Size(kind, size) => ObjectHeaderParser::parse_size(kind, size, buf)
+        }
+    }
+}
+
+impl ObjectHeaderParser {
+    fn parse_kind(mut name: [u8; 6], mut n: u8, buf: &[u8]) -> ParseResult<Self> {
+        let mut buf = buf.iter();
+        while let Some(&b) = buf.next() {
+            if b == b' ' {
+                return match ObjectKind::from_name(&name[..n as usize]) {
+                    Some(kind) => ObjectHeaderParser::parse_size(kind, None, buf.as_slice()),
+                    None => gulp::Result::Err(InvalidObjectHeader::Kind)
+                };
+            }
+            match name.get_mut(n as usize) {
+                Some(c) => *c = b,
+                None => return gulp::Result::Err(InvalidObjectHeader::Kind)
+            }
+            n += 1;
+        }
+        gulp::Result::Incomplete(ObjectHeaderParser(ObjectHeaderParserState::Kind(name, n)))
+    }
+    fn parse_size(kind: ObjectKind, mut size: Option<u64>, buf: &[u8]) -> ParseResult<Self> {
+        let mut buf = buf.iter();
+        while let Some(&b) = buf.next() {
+            size = match (b, size) {
+                (0, Some(size)) => return gulp::Result::Ok(ObjectHeader { kind, size }, buf.as_slice()),
+                // like git, only accept the canonical decimal form
+                (b'0'..=b'9', Some(0)) => return gulp::Result::Err(InvalidObjectHeader::Size),
+                (b'0'..=b'9', size) => {
+                    let digit = (b - b'0') as u64;
+                    match size.unwrap_or(0).checked_mul(10).and_then(|s| s.checked_add(digit)) {
+                        Some(size) => Some(size),
+                        None => return gulp::Result::Err(InvalidObjectHeader::Size)
+                    }
+                }
+                _ => return gulp::Result::Err(InvalidObjectHeader::Size)
+            };
+        }
+        gulp::Result::Incomplete(ObjectHeaderParser(ObjectHeaderParserState::Size(kind, size)))
+    }
+}
+
+pub trait Object {
+    fn kind(&self) -> ObjectKind;
+    fn encode(&self) -> Vec<u8>;
+    fn hash(&self, hash: HashKind) -> Result<ObjectId, Collision> {
+        let body = self.encode();
+        let mut hasher = ObjectHasher::with_hash(hash, ObjectHeader { kind: self.kind(), size: body.len() as u64 });
+        hasher.update(&body);
+        hasher.digest()
+    }
+    fn id(&self) -> Result<ObjectId, Collision> {
+        self.hash(HashKind::Sha1)
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
-pub struct ObjectId(pub [u8; 20]);
+pub enum ObjectId {
+    Sha1([u8; 20]),
+    Sha256([u8; 32])
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct ObjectIdParser(ObjectIdParserState);
+
+#[derive(Debug, Eq, PartialEq)]
+enum ObjectIdParserState {
+    Sha1(gulp::Bytes<[u8; 20]>),
+    Sha256(gulp::Bytes<[u8; 32]>)
+}
 
-#[derive(Default, Debug, Eq, PartialEq)]
-pub struct ObjectIdParser(gulp::Bytes<[u8; 20]>);
+impl ObjectIdParser {
+    pub fn new(hash: HashKind) -> ObjectIdParser {
+        ObjectIdParser(match hash {
+            HashKind::Sha1   => ObjectIdParserState::Sha1(gulp::Bytes::default()),
+            HashKind::Sha256 => ObjectIdParserState::Sha256(gulp::Bytes::default())
+        })
+    }
+}
+
+impl Default for ObjectIdParser {
+    fn default() -> ObjectIdParser {
+        ObjectIdParser::new(HashKind::Sha1)
+    }
+}
 
 impl Parse for ObjectIdParser {
     type Output = ObjectId;
     type Err = Void;
     fn parse(self, buf: &[u8]) -> ParseResult<Self> {
-        match self.0.parse(buf) {
-            gulp::Result::Incomplete(p) => gulp::Result::Incomplete(ObjectIdParser(p)),
-            gulp::Result::Err(e) => match e {},
-            gulp::Result::Ok(buf, tail) => gulp::Result::Ok(ObjectId(buf), tail)
+        match self.0 {
+            ObjectIdParserState::Sha1(p) => match p.parse(buf) {
+                gulp::Result::Incomplete(p) => gulp::Result::Incomplete(ObjectIdParser(ObjectIdParserState::Sha1(p))),
+                gulp::Result::Err(e) => match e {},
+                gulp::Result::Ok(id, tail) => gulp::Result::Ok(ObjectId::Sha1(id), tail)
+            },
+            ObjectIdParserState::Sha256(p) => match p.parse(buf) {
+                gulp::Result::Incomplete(p) => gulp::Result::Incomplete(ObjectIdParser(ObjectIdParserState::Sha256(p))),
+                gulp::Result::Err(e) => match e {},
+                gulp::Result::Ok(id, tail) => gulp::Result::Ok(ObjectId::Sha256(id), tail)
+            }
         }
     }
 }
 
+#[derive(Copy, Clone, Debug, Fail, Eq, PartialEq)]
+pub enum InvalidHex {
+    #[fail(display = "wrong number of hex digits")]
+    Length,
+    #[fail(display = "invalid hex digit")]
+    Digit
+}
+
+impl ObjectId {
+    pub fn null(hash: HashKind) -> ObjectId {
+        match hash {
+            HashKind::Sha1   => ObjectId::Sha1([0; 20]),
+            HashKind::Sha256 => ObjectId::Sha256([0; 32])
+        }
+    }
+    pub fn from_bytes(hash: HashKind, bytes: &[u8]) -> Option<ObjectId> {
+        if bytes.len() != hash.digest_len() {
+            return None;
+        }
+        let mut id = ObjectId::null(hash);
+        id.as_bytes_mut().copy_from_slice(bytes);
+        Some(id)
+    }
+    pub fn from_hex(hex: &[u8]) -> Result<ObjectId, InvalidHex> {
+        let mut id = match hex.len() {
+            40 => ObjectId::null(HashKind::Sha1),
+            64 => ObjectId::null(HashKind::Sha256),
+            _ => return Err(InvalidHex::Length)
+        };
+        for (b, pair) in id.as_bytes_mut().iter_mut().zip(hex.chunks(2)) {
+            let hi = hex_digit(pair[0]).ok_or(InvalidHex::Digit)?;
+            let lo = hex_digit(pair[1]).ok_or(InvalidHex::Digit)?;
+            *b = hi << 4 | lo;
+        }
+        Ok(id)
+    }
+    pub fn hash(&self) -> HashKind {
+        match *self {
+            ObjectId::Sha1(_)   => HashKind::Sha1,
+            ObjectId::Sha256(_) => HashKind::Sha256
+        }
+    }
+    pub fn as_bytes(&self) -> &[u8] {
+        match *self {
+            ObjectId::Sha1(ref id)   => id,
+            ObjectId::Sha256(ref id) => id
+        }
+    }
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        match *self {
+            ObjectId::Sha1(ref mut id)   => id,
+            ObjectId::Sha256(ref mut id) => id
+        }
+    }
+    pub fn is_null(&self) -> bool {
+        self.as_bytes().iter().all(|&b| b == 0)
+    }
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None
+    }
+}
+
+impl FromStr for ObjectId {
+    type Err = InvalidHex;
+    fn from_str(s: &str) -> Result<ObjectId, InvalidHex> {
+        ObjectId::from_hex(s.as_bytes())
+    }
+}
+
 impl fmt::Display for ObjectId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let &ObjectId(ref bytes) = self;
-        for &b in bytes.iter() {
+        for &b in self.as_bytes() {
             write!(f, "{:02x}", b)?;
         }
         Ok(())
@@ -66,18 +289,49 @@ impl fmt::Debug for ObjectId {
     }
 }
 
-pub struct ObjectHasher(sha1dc::Hasher);
+pub struct ObjectHasher(Hasher);
 
 impl ObjectHasher {
     pub fn new(header: ObjectHeader) -> ObjectHasher {
-        let mut h = sha1dc::Hasher::new();
+        ObjectHasher::with_hash(HashKind::Sha1, header)
+    }
+    pub fn with_hash(hash: HashKind, header: ObjectHeader) -> ObjectHasher {
+        let mut h = Hasher::new(hash);
         write!(h, "{} {}\u{0}", header.kind.name(), header.size).unwrap();
         ObjectHasher(h)
     }
     pub fn update(&mut self, buffer: &[u8]) {
         self.0.update(buffer)
     }
-    pub fn digest(self) -> ObjectId {
-        ObjectId(self.0.digest())
+    pub fn digest(self) -> Result<ObjectId, Collision> {
+        self.0.digest()
+    }
+}
+
+#[test]
+fn parse_object_header() {
+    let parse = |s: &[u8]| match ObjectHeaderParser::default().parse(s) {
+        gulp::Result::Ok(header, tail) => Ok((header, tail.len())),
+        gulp::Result::Err(e) => Err(Some(e)),
+        gulp::Result::Incomplete(_) => Err(None)
+    };
+    assert_eq!(parse(b"blob 12\0hello, world"), Ok((ObjectHeader { kind: ObjectKind::Blob, size: 12 }, 12)));
+    assert_eq!(parse(b"commit 0\0"), Ok((ObjectHeader { kind: ObjectKind::Commit, size: 0 }, 0)));
+    assert_eq!(parse(b"tag 18446744073709551615\0"), Ok((ObjectHeader { kind: ObjectKind::Tag, size: u64::MAX }, 0)));
+    assert_eq!(parse(b"tree 12"), Err(None));
+    assert_eq!(parse(b"tree \0"), Err(Some(InvalidObjectHeader::Size)));
+    assert_eq!(parse(b"tree 012\0"), Err(Some(InvalidObjectHeader::Size)));
+    assert_eq!(parse(b"tree 1 \0"), Err(Some(InvalidObjectHeader::Size)));
+    assert_eq!(parse(b"tag 18446744073709551616\0"), Err(Some(InvalidObjectHeader::Size)));
+    assert_eq!(parse(b"blobs 1\0"), Err(Some(InvalidObjectHeader::Kind)));
+    assert_eq!(parse(b"commits 1\0"), Err(Some(InvalidObjectHeader::Kind)));
+
+    let mut parser = ObjectHeaderParser::default();
+    for b in b"blob 1" {
+        parser = match parser.parse(core::slice::from_ref(b)) {
+            gulp::Result::Incomplete(p) => p,
+            _ => unreachable!()
+        };
     }
+    assert_eq!(parser.parse(b"0\0"), gulp::Result::Ok(ObjectHeader { kind: ObjectKind::Blob, size: 10 }, &[][..]));
 }
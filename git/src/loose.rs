@@ -0,0 +1,223 @@
+use std::{fs, process};
+use std::io::{self, Read, Write, BufRead};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use flate2::{Compression, bufread::ZlibDecoder, write::ZlibEncoder};
+use crate::{HashKind, ObjectHasher, ObjectHeader, ObjectHeaderParser, ObjectId, ObjectWriter, Object};
+
+// objects/xx/yyyy…, fanned out on the first byte of the id
+pub fn loose_path(objects: &Path, id: &ObjectId) -> PathBuf {
+    let hex = id.to_string();
+    objects.join(&hex[..2]).join(&hex[2..])
+}
+
+// inflates a loose object, checking its size and id once the body has been read through
+pub struct LooseReader<R: BufRead> {
+    reader: io::BufReader<ZlibDecoder<R>>,
+    header: ObjectHeader,
+    id: ObjectId,
+    hasher: Option<ObjectHasher>,
+    remaining: u64
+}
+
+impl<R: BufRead> LooseReader<R> {
+    pub fn new(id: ObjectId, reader: R) -> io::Result<LooseReader<R>> {
+        let mut reader = io::BufReader::new(ZlibDecoder::new(reader));
+        let header = gulp::from_reader(&mut reader, ObjectHeaderParser::default)?;
+        let hasher = ObjectHasher::with_hash(id.hash(), header);
+        Ok(LooseReader { reader, header, id, hasher: Some(hasher), remaining: header.size })
+    }
+    pub fn header(&self) -> ObjectHeader {
+        self.header
+    }
+    pub fn id(&self) -> ObjectId {
+        self.id
+    }
+    fn finish(&mut self) -> io::Result<()> {
+        let hasher = match self.hasher.take() {
+            Some(hasher) => hasher,
+            None => return Ok(())
+        };
+        if !self.reader.fill_buf()?.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "garbage at end of loose object"));
+        }
+        if hasher.digest()? != self.id {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "loose object doesn't match its id"));
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for LooseReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            self.finish()?;
+            return Ok(0);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len().min(self.remaining.min(usize::MAX as u64) as usize);
+        let n = self.reader.read(&mut buf[..len])?;
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "loose object is shorter than its header says"));
+        }
+        if let Some(ref mut hasher) = self.hasher {
+            hasher.update(&buf[..n]);
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+pub fn open_loose(objects: &Path, id: &ObjectId) -> io::Result<LooseReader<io::BufReader<fs::File>>> {
+    let file = fs::File::open(loose_path(objects, id))?;
+    LooseReader::new(*id, io::BufReader::new(file))
+}
+
+// removes the file when dropped, unless it was kept
+struct TempFile(Option<PathBuf>);
+
+impl TempFile {
+    fn create(dir: &Path) -> io::Result<(TempFile, fs::File)> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        loop {
+            let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+            let path = dir.join(format!("tmp_obj_{}_{}", process::id(), n));
+            match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok((TempFile(Some(path)), file)),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e)
+            }
+        }
+    }
+    fn keep(mut self) -> PathBuf {
+        self.0.take().unwrap()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.0 {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+// compresses an object into a temporary file, which is only renamed into place once it's complete
+pub struct LooseWriter {
+    objects: PathBuf,
+    temp: TempFile,
+    writer: ObjectWriter<ZlibEncoder<fs::File>>,
+    remaining: u64
+}
+
+impl LooseWriter {
+    pub fn new(objects: &Path, header: ObjectHeader) -> io::Result<LooseWriter> {
+        LooseWriter::with_hash(HashKind::Sha1, objects, header)
+    }
+    pub fn with_hash(hash: HashKind, objects: &Path, header: ObjectHeader) -> io::Result<LooseWriter> {
+        LooseWriter::with_compression(hash, objects, header, Compression::fast())
+    }
+    pub fn with_compression(hash: HashKind, objects: &Path, header: ObjectHeader, compression: Compression) -> io::Result<LooseWriter> {
+        let (temp, file) = TempFile::create(objects)?;
+        let mut encoder = ZlibEncoder::new(file, compression);
+        write!(encoder, "{} {}\u{0}", header.kind.name(), header.size)?;
+        let hasher = ObjectHasher::with_hash(hash, header);
+        Ok(LooseWriter {
+            objects: objects.to_owned(),
+            temp,
+            writer: ObjectWriter { hasher, writer: encoder },
+            remaining: header.size
+        })
+    }
+    pub fn finish(self) -> io::Result<ObjectId> {
+        if self.remaining != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "object size doesn't match its header"));
+        }
+        let ObjectWriter { hasher, writer } = self.writer;
+        writer.finish()?.sync_all()?;
+        let id = hasher.digest()?;
+
+        let path = loose_path(&self.objects, &id);
+        fs::create_dir_all(path.parent().unwrap())?;
+        // an object with this id already exists, and is by definition identical
+        if path.exists() {
+            return Ok(id);
+        }
+        fs::rename(self.temp.keep(), &path)?;
+        Ok(id)
+    }
+}
+
+impl Write for LooseWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() as u64 > self.remaining {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "object size doesn't match its header"));
+        }
+        let n = self.writer.write(buf)?;
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+pub fn write_loose<O: Object>(objects: &Path, hash: HashKind, object: &O) -> io::Result<ObjectId> {
+    let body = object.encode();
+    let mut writer = LooseWriter::with_hash(hash, objects, ObjectHeader { kind: object.kind(), size: body.len() as u64 })?;
+    writer.write_all(&body)?;
+    writer.finish()
+}
+
+#[test]
+fn loose_round_trip() {
+    use crate::{Tree, TreeEntry, Mode};
+
+    let objects = std::env::temp_dir().join(format!("git-loose-test-{}", process::id()));
+    fs::create_dir_all(&objects).unwrap();
+
+    let tree = Tree { entries: vec![TreeEntry::new(Mode::Regular, b"a".to_vec(), ObjectId::Sha1([1; 20]))] };
+    for &hash in &[HashKind::Sha1, HashKind::Sha256] {
+        let id = write_loose(&objects, hash, &tree).unwrap();
+        assert_eq!(id, tree.hash(hash).unwrap());
+        assert_eq!(write_loose(&objects, hash, &tree).unwrap(), id);
+
+        let mut reader = open_loose(&objects, &id).unwrap();
+        assert_eq!(reader.header(), ObjectHeader { kind: tree.kind(), size: tree.encode().len() as u64 });
+        assert_eq!(reader.read(&mut []).unwrap(), 0);
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body).unwrap();
+        assert_eq!(body, tree.encode());
+    }
+
+    let mut writer = LooseWriter::new(&objects, ObjectHeader { kind: crate::ObjectKind::Blob, size: 2 }).unwrap();
+    assert!(writer.write_all(b"abc").is_err());
+    writer.write_all(b"a").unwrap();
+    assert!(writer.finish().is_err());
+
+    // a body that doesn't hash to the id it was looked up by
+    let id = "0123456789abcdef0123456789abcdef01234567".parse().unwrap();
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(b"blob 1\0a").unwrap();
+    let data = encoder.finish().unwrap();
+    let mut reader = LooseReader::new(id, &data[..]).unwrap();
+    assert_eq!(reader.read_to_end(&mut Vec::new()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(b"blob 1\0ab").unwrap();
+    let data = encoder.finish().unwrap();
+    let mut reader = LooseReader::new("2e65efe2a145dda7ee51d1741299f848e5bf752e".parse().unwrap(), &data[..]).unwrap();
+    assert_eq!(reader.read_to_end(&mut Vec::new()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(b"blob 2\0a").unwrap();
+    let data = encoder.finish().unwrap();
+    let mut reader = LooseReader::new(id, &data[..]).unwrap();
+    assert_eq!(reader.read_to_end(&mut Vec::new()).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+    let leftovers: Vec<_> = fs::read_dir(&objects).unwrap().map(|e| e.unwrap().file_name()).filter(|n| n.to_string_lossy().starts_with("tmp_obj_")).collect();
+    assert_eq!(leftovers.len(), 0);
+    fs::remove_dir_all(&objects).unwrap();
+}
@@ -0,0 +1,146 @@
+use core::fmt;
+use core::str::FromStr;
+use failure::Fail;
+use crate::{ObjectId, InvalidHex, hex_digit};
+
+// an abbreviated object id, `len` hex digits long, with the digits past that zeroed
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct Prefix {
+    bytes: [u8; 32],
+    len: u8
+}
+
+#[derive(Copy, Clone, Debug, Fail, Eq, PartialEq)]
+pub enum PrefixError {
+    #[fail(display = "no object matches the prefix")]
+    NotFound,
+    #[fail(display = "prefix is ambiguous")]
+    Ambiguous
+}
+
+impl Prefix {
+    pub const MIN_LEN: usize = 4;
+    pub const MAX_LEN: usize = 64;
+
+    pub fn new(id: &ObjectId, len: usize) -> Prefix {
+        let id = id.as_bytes();
+        assert!(len <= id.len() * 2, "prefix longer than its object id");
+        let mut bytes = [0; 32];
+        bytes[..len / 2].copy_from_slice(&id[..len / 2]);
+        if len % 2 == 1 {
+            bytes[len / 2] = id[len / 2] & 0xF0;
+        }
+        Prefix { bytes, len: len as u8 }
+    }
+    pub fn from_hex(hex: &[u8]) -> Result<Prefix, InvalidHex> {
+        if hex.len() < Prefix::MIN_LEN || hex.len() > Prefix::MAX_LEN {
+            return Err(InvalidHex::Length);
+        }
+        let mut bytes = [0; 32];
+        for (i, &c) in hex.iter().enumerate() {
+            let digit = hex_digit(c).ok_or(InvalidHex::Digit)?;
+            bytes[i / 2] |= if i % 2 == 0 { digit << 4 } else { digit };
+        }
+        Ok(Prefix { bytes, len: hex.len() as u8 })
+    }
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub fn matches(&self, id: &ObjectId) -> bool {
+        id.as_bytes().len() * 2 >= self.len() && Prefix::new(id, self.len()) == *self
+    }
+    pub fn resolve(&self, sorted: &[ObjectId]) -> Result<ObjectId, PrefixError> {
+        self.resolve_by_key(sorted, |id| id).copied()
+    }
+    pub fn resolve_by_key<'a, T, F: Fn(&T) -> &ObjectId>(&self, sorted: &'a [T], key: F) -> Result<&'a T, PrefixError> {
+        let start = sorted.partition_point(|t| {
+            let id = key(t).as_bytes();
+            *id < self.bytes[..id.len()]
+        });
+        let mut candidates = sorted[start..].iter().take_while(|t| self.matches(key(t)));
+        match (candidates.next(), candidates.next()) {
+            (Some(t), None) => Ok(t),
+            (Some(_), Some(_)) => Err(PrefixError::Ambiguous),
+            (None, _) => Err(PrefixError::NotFound)
+        }
+    }
+    // the shortest prefix of `id` that no other id in `sorted` shares
+    pub fn shortest(id: &ObjectId, sorted: &[ObjectId], min_len: usize) -> Prefix {
+        Prefix::shortest_by_key(id, sorted, |id| id, min_len)
+    }
+    pub fn shortest_by_key<T, F: Fn(&T) -> &ObjectId>(id: &ObjectId, sorted: &[T], key: F, min_len: usize) -> Prefix {
+        let start = sorted.partition_point(|t| key(t) < id);
+        let end = sorted.partition_point(|t| key(t) <= id);
+        let before = start.checked_sub(1).map(|i| key(&sorted[i]));
+        let after = sorted.get(end).map(&key);
+        let shared = before.into_iter().chain(after).map(|other| common_len(id, other)).max().unwrap_or(0);
+        let max = id.as_bytes().len() * 2;
+        Prefix::new(id, (shared + 1).clamp(min_len.min(max), max))
+    }
+}
+
+fn common_len(a: &ObjectId, b: &ObjectId) -> usize {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    match a.iter().zip(b.iter()).position(|(x, y)| x != y) {
+        Some(i) if (a[i] ^ b[i]) & 0xF0 == 0 => i * 2 + 1,
+        Some(i) => i * 2,
+        None => a.len().min(b.len()) * 2
+    }
+}
+
+impl FromStr for Prefix {
+    type Err = InvalidHex;
+    fn from_str(s: &str) -> Result<Prefix, InvalidHex> {
+        Prefix::from_hex(s.as_bytes())
+    }
+}
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for i in 0..self.len() {
+            let b = self.bytes[i / 2];
+            write!(f, "{:x}", if i % 2 == 0 { b >> 4 } else { b & 0xF })?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Prefix({})", self)
+    }
+}
+
+#[test]
+fn resolve() {
+    use alloc::{string::ToString, vec::Vec};
+
+    let id = |s: &str| s.parse::<ObjectId>().unwrap();
+    let prefix = |s: &str| s.parse::<Prefix>().unwrap();
+    let sorted = [
+        id("0123456789abcdef0123456789abcdef01234567"),
+        id("01234f0000000000000000000000000000000000"),
+        id("89abcdef0123456789abcdef0123456789abcdee"),
+        id("89abcdef0123456789abcdef0123456789abcdef")
+    ];
+
+    assert_eq!(prefix("0123").resolve(&sorted), Err(PrefixError::Ambiguous));
+    assert_eq!(prefix("01234f").resolve(&sorted), Ok(sorted[1]));
+    assert_eq!(prefix("012345").resolve(&sorted), Ok(sorted[0]));
+    assert_eq!(prefix("FFFF").resolve(&sorted), Err(PrefixError::NotFound));
+    assert_eq!(prefix("89abcdef0123456789abcdef0123456789abcdef").resolve(&sorted), Ok(sorted[3]));
+    assert_eq!("012".parse::<Prefix>(), Err(InvalidHex::Length));
+    assert_eq!("012g".parse::<Prefix>(), Err(InvalidHex::Digit));
+
+    let shortest: Vec<_> = sorted.iter().map(|id| Prefix::shortest(id, &sorted, 4).to_string()).collect();
+    assert_eq!(shortest, ["012345", "01234f", "89abcdef0123456789abcdef0123456789abcdee", "89abcdef0123456789abcdef0123456789abcdef"]);
+    assert_eq!(Prefix::shortest(&id("fedcba9876543210fedcba9876543210fedcba98"), &sorted, 7).to_string(), "fedcba9");
+    assert_eq!(Prefix::shortest(&sorted[0], &sorted[..1], 4).to_string(), "0123");
+    assert_eq!(Prefix::shortest(&sorted[0], &sorted[..1], 50).len(), 40);
+    for (id, s) in sorted.iter().zip(&shortest) {
+        assert_eq!(prefix(s).resolve(&sorted), Ok(*id));
+    }
+}
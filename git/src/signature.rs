@@ -0,0 +1,129 @@
+use alloc::vec::Vec;
+use alloc::format;
+use core::convert::TryFrom;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Signature {
+    pub name: Vec<u8>,
+    pub email: Vec<u8>,
+    pub time: Time,
+    // the line as it was read, when writing out the fields wouldn't reproduce it
+    raw: Option<Vec<u8>>
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Time {
+    pub seconds: i64,
+    // minutes east of UTC
+    pub offset: i16
+}
+
+impl Signature {
+    pub fn new(name: Vec<u8>, email: Vec<u8>, time: Time) -> Signature {
+        Signature { name, email, time, raw: None }
+    }
+    // "name <email> seconds +hhmm", read as leniently as git does: it only needs the '<' and '>'.
+    // a missing or unreadable date is the epoch, like git shows it
+    pub fn parse(buf: &[u8]) -> Option<Signature> {
+        let lt = buf.iter().position(|&b| b == b'<')?;
+        let gt = lt + buf[lt..].iter().position(|&b| b == b'>')?;
+        let mut name = &buf[..lt];
+        while let [rest @ .., b' '] = name {
+            name = rest;
+        }
+        let mut signature = Signature::new(name.to_vec(), buf[lt + 1..gt].to_vec(), parse_time(&buf[gt + 1..]));
+        let mut canonical = Vec::with_capacity(buf.len());
+        signature.encode(&mut canonical);
+        if canonical != buf {
+            signature.raw = Some(buf.to_vec());
+        }
+        Some(signature)
+    }
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        // the line as read, for as long as the fields still say the same thing
+        if let Some(ref raw) = self.raw {
+            if let Some(read) = Signature::parse(raw) {
+                if read.name == self.name && read.email == self.email && read.time == self.time {
+                    out.extend_from_slice(raw);
+                    return;
+                }
+            }
+        }
+        out.extend_from_slice(&self.name);
+        out.extend_from_slice(b" <");
+        out.extend_from_slice(&self.email);
+        out.extend_from_slice(b"> ");
+        let sign = if self.time.offset < 0 { '-' } else { '+' };
+        let offset = self.time.offset.unsigned_abs();
+        out.extend_from_slice(format!("{} {}{:02}{:02}", self.time.seconds, sign, offset / 60, offset % 60).as_bytes());
+    }
+}
+
+fn parse_time(buf: &[u8]) -> Time {
+    let epoch = Time { seconds: 0, offset: 0 };
+    let buf = skip_spaces(buf);
+    let digits = buf.iter().take_while(|b| b.is_ascii_digit()).count();
+    let seconds = match parse_decimal(&buf[..digits]) {
+        Some(seconds) => seconds,
+        None => return epoch
+    };
+    let tz = skip_spaces(&buf[digits..]);
+    let (sign, tz) = match tz.split_first() {
+        Some((&b'+', tz)) => (1, tz),
+        Some((&b'-', tz)) => (-1, tz),
+        _ => return Time { seconds, offset: 0 }
+    };
+    // git reads however many digits there are as hhmm
+    let digits = tz.iter().take_while(|b| b.is_ascii_digit()).count();
+    let offset = parse_decimal(&tz[..digits])
+        .map(|hhmm| sign * (hhmm / 100 * 60 + hhmm % 100))
+        .and_then(|minutes| i16::try_from(minutes).ok());
+    Time { seconds, offset: offset.unwrap_or(0) }
+}
+
+fn skip_spaces(mut buf: &[u8]) -> &[u8] {
+    while let [b' ', rest @ ..] = buf {
+        buf = rest;
+    }
+    buf
+}
+
+fn parse_decimal(buf: &[u8]) -> Option<i64> {
+    if buf.is_empty() {
+        return None;
+    }
+    buf.iter().try_fold(0i64, |n, &b| match b {
+        b'0'..=b'9' => n.checked_mul(10)?.checked_add((b - b'0') as i64),
+        _ => None
+    })
+}
+
+#[test]
+fn parse_signature() {
+    let time = |seconds, offset| Time { seconds, offset };
+    let cases: [(&[u8], Time); 7] = [
+        (b"A U Thor <author@example.com> 1112911993 -0700", time(1112911993, -420)),
+        (b"A U Thor <author@example.com> 1112911993 -0000", time(1112911993, 0)),
+        (b"A U Thor<author@example.com> 1112911993 +0000", time(1112911993, 0)),
+        (b"A U Thor  <author@example.com>  1112911993  +0530", time(1112911993, 330)),
+        (b"A U Thor <author@example.com> 1112911993 +530", time(1112911993, 330)),
+        (b"A U Thor <author@example.com> 1112911993", time(1112911993, 0)),
+        (b"A U Thor <author@example.com> 99999999999999999999 +0000", time(0, 0))
+    ];
+    for &(line, time) in &cases {
+        let signature = Signature::parse(line).unwrap();
+        assert_eq!((&signature.name[..], &signature.email[..], signature.time), (&b"A U Thor"[..], &b"author@example.com"[..], time));
+        let mut out = Vec::new();
+        signature.encode(&mut out);
+        assert_eq!(out, line);
+    }
+
+    // changing a field drops the original layout
+    let mut signature = Signature::parse(b"A U Thor<author@example.com> 1112911993 -0000").unwrap();
+    signature.name = b"A N Other".to_vec();
+    let mut out = Vec::new();
+    signature.encode(&mut out);
+    assert_eq!(out, b"A N Other <author@example.com> 1112911993 +0000");
+    assert_eq!(Signature::parse(b"<>").map(|s| s.time), Some(time(0, 0)));
+    assert_eq!(Signature::parse(b"A U Thor author@example.com 0 +0000"), None);
+}
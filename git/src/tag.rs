@@ -0,0 +1,207 @@
+use alloc::vec::Vec;
+use alloc::format;
+use failure::Fail;
+use gulp::{Parse, ParseResult};
+use crate::{Object, ObjectId, ObjectKind, Signature};
+use crate::fields::{Fields, FieldsParser, parse_id, encode_field, encode_message};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Tag {
+    pub object: ObjectId,
+    pub kind: ObjectKind,
+    pub name: Vec<u8>,
+    pub tagger: Option<Signature>,
+    // everything after the tagger, including a tagger line too mangled to read
+    pub extra: Vec<(Vec<u8>, Vec<u8>)>,
+    // the message with any trailing signature block split off into `signature`
+    pub message: Option<Vec<u8>>,
+    pub signature: Option<Vec<u8>>
+}
+
+impl Object for Tag {
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Tag
+    }
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_field(&mut out, b"object", format!("{}", self.object).as_bytes());
+        encode_field(&mut out, b"type", self.kind.name().as_bytes());
+        encode_field(&mut out, b"tag", &self.name);
+        if let Some(ref tagger) = self.tagger {
+            let mut signature = Vec::new();
+            tagger.encode(&mut signature);
+            encode_field(&mut out, b"tagger", &signature);
+        }
+        for (key, value) in &self.extra {
+            encode_field(&mut out, key, value);
+        }
+        encode_message(&mut out, self.message.as_deref());
+        if let Some(ref signature) = self.signature {
+            out.extend_from_slice(signature);
+        }
+        out
+    }
+}
+
+const SIGNATURE_MARKERS: [&[u8]; 4] = [
+    b"-----BEGIN PGP SIGNATURE-----",
+    b"-----BEGIN PGP MESSAGE-----",
+    b"-----BEGIN SSH SIGNATURE-----",
+    b"-----BEGIN SIGNED MESSAGE-----"
+];
+
+// like git, the signature starts at the last line that opens one
+fn signature_start(message: &[u8]) -> Option<usize> {
+    let mut start = None;
+    let mut line = 0;
+    while line < message.len() {
+        if SIGNATURE_MARKERS.iter().any(|m| message[line..].starts_with(m)) {
+            start = Some(line);
+        }
+        line = match message[line..].iter().position(|&b| b == b'\n') {
+            Some(i) => line + i + 1,
+            None => message.len()
+        };
+    }
+    start
+}
+
+#[derive(Copy, Clone, Debug, Fail, Eq, PartialEq)]
+pub enum InvalidTag {
+    #[fail(display = "malformed tag header line")]
+    Header,
+    #[fail(display = "missing or invalid tag object")]
+    Object,
+    #[fail(display = "missing or invalid tag type")]
+    Type,
+    #[fail(display = "missing tag name")]
+    Name
+}
+
+#[derive(Default, Debug, Eq, PartialEq)]
+struct TagFields {
+    object: Option<ObjectId>,
+    kind: Option<ObjectKind>,
+    name: Option<Vec<u8>>,
+    tagger: Option<Signature>,
+    extra: Vec<(Vec<u8>, Vec<u8>)>
+}
+
+impl Fields for TagFields {
+    type Output = Tag;
+    type Err = InvalidTag;
+    fn malformed() -> InvalidTag {
+        InvalidTag::Header
+    }
+    fn field(&mut self, key: &[u8], value: Vec<u8>) -> Result<(), InvalidTag> {
+        if self.object.is_none() {
+            if key != b"object" {
+                return Err(InvalidTag::Object);
+            }
+            self.object = Some(parse_id(&value).ok_or(InvalidTag::Object)?);
+        } else if self.kind.is_none() {
+            if key != b"type" {
+                return Err(InvalidTag::Type);
+            }
+            self.kind = Some(ObjectKind::from_name(&value).ok_or(InvalidTag::Type)?);
+        } else if self.name.is_none() {
+            if key != b"tag" {
+                return Err(InvalidTag::Name);
+            }
+            self.name = Some(value);
+        } else if key == b"tagger" && self.tagger.is_none() && self.extra.is_empty() {
+            match Signature::parse(&value) {
+                Some(tagger) => self.tagger = Some(tagger),
+                None => self.extra.push((key.to_vec(), value))
+            }
+        } else {
+            self.extra.push((key.to_vec(), value));
+        }
+        Ok(())
+    }
+    fn finish(self, message: Option<Vec<u8>>) -> Result<Tag, InvalidTag> {
+        let (message, signature) = match message {
+            Some(mut message) => match signature_start(&message) {
+                Some(i) => {
+                    let signature = message.split_off(i);
+                    (Some(message), Some(signature))
+                }
+                None => (Some(message), None)
+            },
+            None => (None, None)
+        };
+        Ok(Tag {
+            object: self.object.ok_or(InvalidTag::Object)?,
+            kind: self.kind.ok_or(InvalidTag::Type)?,
+            name: self.name.ok_or(InvalidTag::Name)?,
+            tagger: self.tagger,
+            extra: self.extra,
+            message,
+            signature
+        })
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct TagParser(FieldsParser<TagFields>);
+
+impl TagParser {
+    pub fn new(size: u64) -> TagParser {
+        TagParser(FieldsParser::new(TagFields::default(), size))
+    }
+}
+
+impl Parse for TagParser {
+    type Output = Tag;
+    type Err = InvalidTag;
+    fn parse(self, buf: &[u8]) -> ParseResult<Self> {
+        match self.0.parse(buf) {
+            gulp::Result::Incomplete(p) => gulp::Result::Incomplete(TagParser(p)),
+            gulp::Result::Err(e) => gulp::Result::Err(e),
+            gulp::Result::Ok(tag, tail) => gulp::Result::Ok(tag, tail)
+        }
+    }
+}
+
+#[test]
+fn parse_tag() {
+    let parse = |body: &[u8]| match TagParser::new(body.len() as u64).parse(body) {
+        gulp::Result::Ok(tag, _) => Ok(tag),
+        gulp::Result::Err(e) => Err(e),
+        gulp::Result::Incomplete(_) => unreachable!()
+    };
+
+    let body: &[u8] = b"object 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+        type tree\n\
+        tag v1.0\n\
+        tagger C O Mitter <committer@example.com> 1112912053 +0000\n\
+        \n\
+        release\n-----BEGIN PGP SIGNATURE-----\nnot really\n-----END PGP SIGNATURE-----\n";
+    let tag = parse(body).unwrap();
+    assert_eq!(tag.encode(), body);
+    assert_eq!(tag.object, ObjectId::from_hex(b"4b825dc642cb6eb9a060e54bf8d69288fbee4904").unwrap());
+    assert_eq!(tag.kind, ObjectKind::Tree);
+    assert_eq!(tag.name, b"v1.0");
+    assert_eq!(tag.tagger.unwrap().name, b"C O Mitter");
+    assert_eq!(tag.message, Some(b"release\n".to_vec()));
+    assert_eq!(tag.signature, Some(b"-----BEGIN PGP SIGNATURE-----\nnot really\n-----END PGP SIGNATURE-----\n".to_vec()));
+
+    let old = parse(b"object 4b825dc642cb6eb9a060e54bf8d69288fbee4904\ntype commit\ntag v0.99\n\nno tagger\n").unwrap();
+    assert_eq!(old.tagger, None);
+    assert_eq!(old.signature, None);
+    assert_eq!(parse(b"object 4b825dc642cb6eb9a060e54bf8d69288fbee4904\ntype thing\ntag x\n"), Err(InvalidTag::Type));
+    assert_eq!(parse(b"object 4b825dc642cb6eb9a060e54bf8d69288fbee4904\ntype blob\n"), Err(InvalidTag::Name));
+    assert_eq!(parse(b"type blob\n"), Err(InvalidTag::Object));
+    assert_eq!(parse(b"object 4B825DC642CB6EB9A060E54BF8D69288FBEE4904\ntype blob\ntag x\n"), Err(InvalidTag::Object));
+
+    let odd: &[u8] = b"object 4b825dc642cb6eb9a060e54bf8d69288fbee4904\ntype tree\ntag v0.1\ntagger C O Mitter<committer@example.com> 1112912053 -0000\n\nodd\n";
+    let tag = parse(odd).unwrap();
+    assert_eq!(tag.encode(), odd);
+    assert_eq!(format!("{}", tag.id().unwrap()), "d1c8c9485b54fdca5a8983f5183d5b0cc87edd3b");
+
+    let mangled: &[u8] = b"object 4b825dc642cb6eb9a060e54bf8d69288fbee4904\ntype blob\ntag x\ntagger nobody\n\nmangled\n";
+    let tag = parse(mangled).unwrap();
+    assert_eq!(tag.tagger, None);
+    assert_eq!(tag.extra, [(b"tagger".to_vec(), b"nobody".to_vec())]);
+    assert_eq!(tag.encode(), mangled);
+}
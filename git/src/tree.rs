@@ -0,0 +1,412 @@
+use alloc::vec::Vec;
+use alloc::format;
+use core::cmp::{self, Ordering};
+use failure::Fail;
+use gulp::{Parse, ParseResult};
+use crate::{HashKind, Object, ObjectId, ObjectIdParser, ObjectKind};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Mode {
+    Regular,
+    Executable,
+    Symlink,
+    Gitlink,
+    Directory
+}
+
+impl Mode {
+    pub fn from_bits(bits: u32) -> Option<Mode> {
+        match bits {
+            0o100644 => Some(Mode::Regular),
+            0o100755 => Some(Mode::Executable),
+            0o120000 => Some(Mode::Symlink),
+            0o160000 => Some(Mode::Gitlink),
+            0o040000 => Some(Mode::Directory),
+            _ => None
+        }
+    }
+    // what git makes of any other mode: the file type decides, and only a file's executable bit survives
+    pub fn canonicalize(bits: u32) -> Mode {
+        match bits & 0o170000 {
+            0o100000 if bits & 0o100 != 0 => Mode::Executable,
+            0o100000 => Mode::Regular,
+            0o120000 => Mode::Symlink,
+            0o040000 => Mode::Directory,
+            _ => Mode::Gitlink
+        }
+    }
+    pub fn bits(&self) -> u32 {
+        match *self {
+            Mode::Regular    => 0o100644,
+            Mode::Executable => 0o100755,
+            Mode::Symlink    => 0o120000,
+            Mode::Gitlink    => 0o160000,
+            Mode::Directory  => 0o040000
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TreeEntry {
+    pub mode: Mode,
+    pub name: Vec<u8>,
+    pub id: ObjectId,
+    // the mode as it was written, when that isn't how git would write `mode`
+    raw_mode: Option<Vec<u8>>
+}
+
+impl TreeEntry {
+    pub fn new(mode: Mode, name: Vec<u8>, id: ObjectId) -> TreeEntry {
+        TreeEntry { mode, name, id, raw_mode: None }
+    }
+    // the mode as it's written out: as it was read, for as long as it still means `mode`
+    pub fn raw_mode(&self) -> Vec<u8> {
+        match self.raw_mode {
+            Some(ref raw) if raw_mode_bits(raw).map(Mode::canonicalize) == Some(self.mode) => raw.clone(),
+            _ => format!("{:o}", self.mode.bits()).into_bytes()
+        }
+    }
+}
+
+fn raw_mode_bits(raw: &[u8]) -> Option<u32> {
+    raw.iter().try_fold(0u32, |bits, &b| match b {
+        b'0'..=b'7' if bits >> 29 == 0 => Some(bits << 3 | (b - b'0') as u32),
+        _ => None
+    })
+}
+
+#[derive(Copy, Clone, Debug, Fail, Eq, PartialEq)]
+pub enum InvalidTree {
+    #[fail(display = "invalid tree entry mode")]
+    Mode,
+    #[fail(display = "invalid tree entry name")]
+    Name,
+    #[fail(display = "tree entries out of order")]
+    Order,
+    #[fail(display = "duplicate tree entry")]
+    Duplicate,
+    #[fail(display = "truncated tree entry")]
+    Truncated
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct TreeEntryParser(HashKind, TreeEntryParserState);
+
+#[derive(Debug, Eq, PartialEq)]
+enum TreeEntryParserState {
+    Mode(u32, u8),
+    Name(RawMode, Vec<u8>),
+    Id(RawMode, Vec<u8>, ObjectIdParser)
+}
+
+// the mode's bits and how many digits they were written with
+type RawMode = (u32, u8);
+
+impl TreeEntryParser {
+    pub fn new(hash: HashKind) -> TreeEntryParser {
+        TreeEntryParser(hash, TreeEntryParserState::Mode(0, 0))
+    }
+}
+
+impl Default for TreeEntryParser {
+    fn default() -> TreeEntryParser {
+        TreeEntryParser::new(HashKind::Sha1)
+    }
+}
+
+impl Parse for TreeEntryParser {
+    type Output = TreeEntry;
+    type Err = InvalidTree;
+    fn parse(self, buf: &[u8]) -> ParseResult<Self> {
+        let hash = self.0;
+        match self.1 {
+            TreeEntryParserState::Mode(bits, n)      => TreeEntryParser::parse_mode(hash, bits, n, buf),
+            TreeEntryParserState::Name(mode, name)   => TreeEntryParser::parse_name(hash, mode, name, buf),
+            TreeEntryParserState::Id(mode, name, p)  => TreeEntryParser::parse_id(hash, mode, name, p, buf)
+        }
+    }
+}
+
+impl TreeEntryParser {
+    // like git, any octal number will do: zero padded or odd modes are left to `TreeValidator`
+    fn parse_mode(hash: HashKind, mut bits: u32, mut n: u8, buf: &[u8]) -> ParseResult<Self> {
+        let mut buf = buf.iter();
+        while let Some(&b) = buf.next() {
+            match b {
+                b'0'..=b'7' if bits >> 29 == 0 && n < u8::MAX => {
+                    bits = bits << 3 | (b - b'0') as u32;
+                    n += 1;
+                }
+                b' ' => return TreeEntryParser::parse_name(hash, (bits, n), Vec::new(), buf.as_slice()),
+                _ => return gulp::Result::Err(InvalidTree::Mode)
+            }
+        }
+        gulp::Result::Incomplete(TreeEntryParser(hash, TreeEntryParserState::Mode(bits, n)))
+    }
+    fn parse_name(hash: HashKind, mode: RawMode, mut name: Vec<u8>, buf: &[u8]) -> ParseResult<Self> {
+        match buf.iter().position(|&b| b == 0) {
+            None => {
+                name.extend_from_slice(buf);
+                gulp::Result::Incomplete(TreeEntryParser(hash, TreeEntryParserState::Name(mode, name)))
+            }
+            Some(i) => {
+                name.extend_from_slice(&buf[..i]);
+                // git can't read an entry without a name
+                if name.is_empty() {
+                    return gulp::Result::Err(InvalidTree::Name);
+                }
+                TreeEntryParser::parse_id(hash, mode, name, ObjectIdParser::new(hash), &buf[i + 1..])
+            }
+        }
+    }
+    fn parse_id(hash: HashKind, (bits, n): RawMode, name: Vec<u8>, p: ObjectIdParser, buf: &[u8]) -> ParseResult<Self> {
+        match p.parse(buf) {
+            gulp::Result::Incomplete(p) => gulp::Result::Incomplete(TreeEntryParser(hash, TreeEntryParserState::Id((bits, n), name, p))),
+            gulp::Result::Err(e) => match e {},
+            gulp::Result::Ok(id, tail) => {
+                let mut entry = TreeEntry::new(Mode::canonicalize(bits), name, id);
+                let raw = if n == 0 { Vec::new() } else { format!("{:01$o}", bits, n as usize).into_bytes() };
+                if raw != entry.raw_mode() {
+                    entry.raw_mode = Some(raw);
+                }
+                gulp::Result::Ok(entry, tail)
+            }
+        }
+    }
+}
+
+// checks the entries of one tree in order, rejecting what a strict git fsck would.
+// parsing doesn't, since git still reads trees with these problems
+#[derive(Default, Debug, Eq, PartialEq)]
+pub struct TreeValidator {
+    last: Option<(Vec<u8>, Mode)>
+}
+
+impl TreeValidator {
+    pub fn new() -> TreeValidator {
+        TreeValidator::default()
+    }
+    pub fn entry(&mut self, entry: &TreeEntry) -> Result<(), InvalidTree> {
+        let name = &entry.name[..];
+        if name.is_empty() || name == b"." || name == b".." || is_dotgit(name) || name.contains(&b'/') {
+            return Err(InvalidTree::Name);
+        }
+        // zero padded or otherwise unusual modes, like the 100664 of very old trees
+        if entry.raw_mode() != format!("{:o}", entry.mode.bits()).into_bytes() {
+            return Err(InvalidTree::Mode);
+        }
+        if let Some((ref last, last_mode)) = self.last {
+            if last[..] == *name {
+                return Err(InvalidTree::Duplicate);
+            }
+            if compare_entries(last, last_mode, name, entry.mode) != Ordering::Less {
+                return Err(InvalidTree::Order);
+            }
+        }
+        self.last = Some((entry.name.clone(), entry.mode));
+        Ok(())
+    }
+}
+
+// any name that some filesystem would take for .git, as git's fsck checks for
+fn is_dotgit(name: &[u8]) -> bool {
+    name.eq_ignore_ascii_case(b".git") || is_ntfs_dotgit(name) || is_hfs_dotgit(name)
+}
+
+// NTFS drops trailing dots and spaces, starts a stream name at ':', splits paths at '\',
+// and gives .git the short name git~1
+fn is_ntfs_dotgit(name: &[u8]) -> bool {
+    let end = name.iter().position(|&b| b == b'\\' || b == b':').unwrap_or(name.len());
+    let padded = |prefix: &[u8]| end >= prefix.len()
+        && name[..prefix.len()].eq_ignore_ascii_case(prefix)
+        && name[prefix.len()..end].iter().all(|&b| b == b' ' || b == b'.');
+    padded(b".git") || padded(b"git~1")
+}
+
+// HFS+ skips some zero-width code points when comparing names.
+// like git, the name ends at any malformed UTF-8
+fn is_hfs_dotgit(name: &[u8]) -> bool {
+    let name = match core::str::from_utf8(name) {
+        Ok(name) => name,
+        Err(e) => core::str::from_utf8(&name[..e.valid_up_to()]).unwrap()
+    };
+    let mut chars = name.chars()
+        .filter(|c| !matches!(c, '\u{200c}'..='\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{206a}'..='\u{206f}' | '\u{feff}'))
+        .map(|c| c.to_ascii_lowercase());
+    ".git".chars().all(|c| chars.next() == Some(c)) && chars.next().is_none()
+}
+
+// directories sort as if their name ended in a slash
+fn compare_entries(a: &[u8], a_mode: Mode, b: &[u8], b_mode: Mode) -> Ordering {
+    let n = cmp::min(a.len(), b.len());
+    match a[..n].cmp(&b[..n]) {
+        Ordering::Equal => {}
+        ord => return ord
+    }
+    let next = |name: &[u8], mode| match name.get(n) {
+        Some(&c) => c,
+        None if mode == Mode::Directory => b'/',
+        None => 0
+    };
+    next(a, a_mode).cmp(&next(b, b_mode))
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Tree {
+    pub entries: Vec<TreeEntry>
+}
+
+impl Tree {
+    pub fn validate(&self) -> Result<(), InvalidTree> {
+        let mut validator = TreeValidator::new();
+        self.entries.iter().try_for_each(|entry| validator.entry(entry))
+    }
+}
+
+impl Object for Tree {
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Tree
+    }
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for entry in &self.entries {
+            out.extend_from_slice(&entry.raw_mode());
+            out.push(b' ');
+            out.extend_from_slice(&entry.name);
+            out.push(0);
+            out.extend_from_slice(entry.id.as_bytes());
+        }
+        out
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct TreeParser {
+    hash: HashKind,
+    remaining: u64,
+    entry: TreeEntryParser,
+    entries: Vec<TreeEntry>
+}
+
+impl TreeParser {
+    pub fn new(size: u64) -> TreeParser {
+        TreeParser::with_hash(HashKind::Sha1, size)
+    }
+    pub fn with_hash(hash: HashKind, size: u64) -> TreeParser {
+        TreeParser { hash, remaining: size, entry: TreeEntryParser::new(hash), entries: Vec::new() }
+    }
+}
+
+impl Parse for TreeParser {
+    type Output = Tree;
+    type Err = InvalidTree;
+    fn parse(mut self, buf: &[u8]) -> ParseResult<Self> {
+        let n = cmp::min(buf.len() as u64, self.remaining) as usize;
+        let (mut buf, tail) = buf.split_at(n);
+        while !buf.is_empty() {
+            match self.entry.parse(buf) {
+                gulp::Result::Incomplete(p) => {
+                    self.entry = p;
+                    self.remaining -= buf.len() as u64;
+                    buf = &[];
+                }
+                gulp::Result::Err(e) => return gulp::Result::Err(e),
+                gulp::Result::Ok(entry, rest) => {
+                    self.entries.push(entry);
+                    self.entry = TreeEntryParser::new(self.hash);
+                    self.remaining -= (buf.len() - rest.len()) as u64;
+                    buf = rest;
+                }
+            }
+        }
+        if self.remaining != 0 {
+            return gulp::Result::Incomplete(self);
+        }
+        if self.entry != TreeEntryParser::new(self.hash) {
+            return gulp::Result::Err(InvalidTree::Truncated);
+        }
+        gulp::Result::Ok(Tree { entries: self.entries }, tail)
+    }
+}
+
+#[test]
+fn parse_tree() {
+    let entry = |mode: &str, name: &str| {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(mode.as_bytes());
+        buf.push(b' ');
+        buf.extend_from_slice(name.as_bytes());
+        buf.push(0);
+        buf.extend_from_slice(&[name.len() as u8; 20]);
+        buf
+    };
+    let parse = |entries: &[Vec<u8>]| {
+        let body = entries.concat();
+        let mut parser = TreeParser::new(body.len() as u64);
+        for (i, b) in body.iter().enumerate() {
+            parser = match parser.parse(core::slice::from_ref(b)) {
+                gulp::Result::Incomplete(p) => p,
+                gulp::Result::Ok(tree, _) if i == body.len() - 1 => return Ok(tree),
+                gulp::Result::Ok(..) => unreachable!(),
+                gulp::Result::Err(e) => return Err(e)
+            };
+        }
+        match parser.parse(&[]) {
+            gulp::Result::Ok(tree, _) => Ok(tree),
+            gulp::Result::Err(e) => Err(e),
+            gulp::Result::Incomplete(_) => unreachable!()
+        }
+    };
+
+    let entries = [entry("100644", "a-b"), entry("40000", "a"), entry("120000", "a0"), entry("160000", "b"), entry("100755", "c")];
+    let tree = parse(&entries).unwrap();
+    assert_eq!(tree.encode(), entries.concat());
+    let modes: Vec<_> = tree.entries.iter().map(|e| e.mode).collect();
+    assert_eq!(modes, [Mode::Regular, Mode::Directory, Mode::Symlink, Mode::Gitlink, Mode::Executable]);
+    assert_eq!(tree.entries[1], TreeEntry::new(Mode::Directory, b"a".to_vec(), ObjectId::Sha1([1; 20])));
+    assert_eq!(tree.validate(), Ok(()));
+    assert_eq!(parse(&[]).unwrap().entries, []);
+    assert_eq!(format!("{}", parse(&[]).unwrap().id().unwrap()), "4b825dc642cb6eb9a060e54bf8d69288fbee4904");
+    assert_eq!(format!("{}", parse(&[]).unwrap().hash(HashKind::Sha256).unwrap()), "6ef19b41225c5369f1c104d45d8d85efa9b057b53b14b4b9b939dd74decc5321");
+
+    // git reads these, and so do we, but fsck would complain
+    let validate = |entries: &[Vec<u8>]| parse(entries).unwrap().validate();
+    assert_eq!(validate(&[entry("100644", "a"), entry("100644", "a-b")]), Ok(()));
+    assert_eq!(validate(&[entry("40000", "a"), entry("100644", "a-b")]), Err(InvalidTree::Order));
+    assert_eq!(validate(&[entry("100644", "a"), entry("100644", "a")]), Err(InvalidTree::Duplicate));
+    assert_eq!(validate(&[entry("40000", ".GIT")]), Err(InvalidTree::Name));
+    for &name in &[".git. .", "GIT~1", "git~1 ", ".git::$INDEX_ALLOCATION", ".git\\hooks", ".g\u{200c}it", "\u{feff}.GIT", ".git\u{206f}"] {
+        assert_eq!(validate(&[entry("40000", name)]), Err(InvalidTree::Name), "{}", name);
+    }
+    for &name in &[".gitignore", ".git~1", "git~2", ".git-x", ".g\u{200b}it", "git"] {
+        assert_eq!(validate(&[entry("40000", name)]), Ok(()), "{}", name);
+    }
+    assert_eq!(validate(&[entry("100644", "..")]), Err(InvalidTree::Name));
+    assert_eq!(validate(&[entry("040000", "a")]), Err(InvalidTree::Mode));
+    assert_eq!(validate(&[entry("100664", "a")]), Err(InvalidTree::Mode));
+
+    // and odd modes are written back the way they were read
+    let odd = [entry("040000", "a"), entry("100664", "b"), entry("100775", "c"), entry("0120000", "d"), entry("100000", "e")];
+    let tree = parse(&odd).unwrap();
+    let modes: Vec<_> = tree.entries.iter().map(|e| e.mode).collect();
+    assert_eq!(modes, [Mode::Directory, Mode::Regular, Mode::Executable, Mode::Symlink, Mode::Regular]);
+    assert_eq!(tree.entries[0].raw_mode(), b"040000");
+    assert_eq!(tree.encode(), odd.concat());
+    let mut tree = tree;
+    tree.entries[1].mode = Mode::Executable;
+    assert_eq!(tree.entries[1].raw_mode(), b"100755");
+
+    // from the same kind of tree in a real repository, down to the id
+    let mut odd = Vec::new();
+    for &(mode, name, id) in &[("040000", "a", "4b825dc642cb6eb9a060e54bf8d69288fbee4904"), ("100664", "b", "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"), ("100644", "c", "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391")] {
+        odd.extend_from_slice(format!("{} {}\0", mode, name).as_bytes());
+        odd.extend_from_slice(ObjectId::from_hex(id.as_bytes()).unwrap().as_bytes());
+    }
+    let tree = parse(&[odd.clone()]).unwrap();
+    assert_eq!(tree.encode(), odd);
+    assert_eq!(format!("{}", tree.id().unwrap()), "1b8dafb4d98e666ee9718cb56b87582d7e9148e1");
+
+    assert_eq!(parse(&[entry("100644", "")]), Err(InvalidTree::Name));
+    assert_eq!(parse(&[entry("100844", "a")]), Err(InvalidTree::Mode));
+    assert_eq!(parse(&[entry("100644", "a")[..10].to_vec()]), Err(InvalidTree::Truncated));
+}
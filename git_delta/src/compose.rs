@@ -0,0 +1,188 @@
+use std::cmp::min;
+use std::io::{self, BufRead};
+use crate::{Header, Command, InvalidDelta};
+use crate::io::{Commands, invalid_data};
+use crate::encode::{emit_inserts, emit_copies};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Segment {
+    Copy { off: u64, len: u64 },
+    Insert { start: usize, len: usize }
+}
+
+impl Segment {
+    fn len(&self) -> u64 {
+        match *self {
+            Segment::Copy { len, .. } => len,
+            Segment::Insert { len, .. } => len as u64
+        }
+    }
+    fn slice(&self, skip: u64, len: u64) -> Segment {
+        match *self {
+            Segment::Copy { off, .. } => Segment::Copy { off: off + skip, len },
+            Segment::Insert { start, .. } => Segment::Insert { start: start + skip as usize, len: len as usize }
+        }
+    }
+}
+
+// the result of every delta pushed so far, as ranges of the root base and of literal data
+pub struct Composer {
+    header: Header,
+    segments: Vec<Segment>,
+    starts: Vec<u64>,
+    data: Vec<u8>
+}
+
+impl Composer {
+    pub fn new<D: BufRead>(delta: D) -> io::Result<Composer> {
+        let mut commands = Commands::new(delta)?;
+        let base_len = commands.header.base_len;
+        commands.strict(base_len)?;
+        let mut composer = Composer { header: commands.header, segments: Vec::new(), starts: Vec::new(), data: Vec::new() };
+        while commands.next()? {
+            let segment = match commands.command {
+                Command::Copy { off, len } => Segment::Copy { off: off as u64, len: len as u64 },
+                Command::Insert { .. } => composer.read_insert(&mut commands)?
+            };
+            composer.push_segment(segment);
+        }
+        Ok(composer)
+    }
+    pub fn header(&self) -> Header {
+        self.header
+    }
+    pub fn push<D: BufRead>(&mut self, delta: D) -> io::Result<()> {
+        let mut commands = Commands::new(delta)?;
+        commands.strict(self.header.result_len)?;
+        let segments = std::mem::take(&mut self.segments);
+        let starts = std::mem::take(&mut self.starts);
+        self.header.result_len = commands.header.result_len;
+        while commands.next()? {
+            match commands.command {
+                Command::Copy { off, len } => {
+                    let (mut off, mut len) = (off as u64, len as u64);
+                    // strict validation keeps copies inside the previous result, but don't lean on it
+                    let mut i = starts.partition_point(|&s| s <= off).checked_sub(1)
+                        .ok_or_else(|| invalid_data(InvalidDelta::CopyRange))?;
+                    while len > 0 {
+                        let segment = segments.get(i).ok_or_else(|| invalid_data(InvalidDelta::CopyRange))?;
+                        let skip = off - starts[i];
+                        let n = min(len, segment.len() - skip);
+                        self.push_segment(segment.slice(skip, n));
+                        off += n;
+                        len -= n;
+                        i += 1;
+                    }
+                }
+                Command::Insert { .. } => {
+                    let segment = self.read_insert(&mut commands)?;
+                    self.push_segment(segment);
+                }
+            }
+        }
+        self.compact();
+        Ok(())
+    }
+    pub fn encode(&self) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut buf = [0; Header::MAX_LEN];
+        out.extend_from_slice(self.header.encode(&mut buf));
+        for segment in &self.segments {
+            match *segment {
+                Segment::Copy { off, len } => emit_copies(&mut out, off, len)?,
+                Segment::Insert { start, len } => emit_inserts(&mut out, &self.data[start..start + len])
+            }
+        }
+        Ok(out)
+    }
+    fn read_insert<D: BufRead>(&mut self, commands: &mut Commands<D>) -> io::Result<Segment> {
+        let start = self.data.len();
+        let mut len = commands.command.len() as u8;
+        let mut buf = [0; Command::MAX_INSERT as usize];
+        while len > 0 {
+            match commands.read_insert(&mut len, &mut buf)? {
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => self.data.extend_from_slice(&buf[..n])
+            }
+        }
+        Ok(Segment::Insert { start, len: self.data.len() - start })
+    }
+    // drops the inserted bytes that the latest delta no longer copies, so a long chain doesn't pile them up
+    fn compact(&mut self) {
+        let segments = std::mem::take(&mut self.segments);
+        let data = std::mem::take(&mut self.data);
+        self.starts.clear();
+        for segment in segments {
+            let segment = match segment {
+                Segment::Insert { start, len } => {
+                    self.data.extend_from_slice(&data[start..start + len]);
+                    Segment::Insert { start: self.data.len() - len, len }
+                }
+                copy => copy
+            };
+            self.push_segment(segment);
+        }
+    }
+    fn push_segment(&mut self, segment: Segment) {
+        let start = self.starts.last().map_or(0, |&s| s) + self.segments.last().map_or(0, |s| s.len());
+        match (self.segments.last_mut(), segment) {
+            (Some(Segment::Copy { off, len }), Segment::Copy { off: next, len: n }) if *off + *len == next => *len += n,
+            (Some(Segment::Insert { start, len }), Segment::Insert { start: next, len: n }) if *start + *len == next => *len += n,
+            _ => {
+                self.segments.push(segment);
+                self.starts.push(start);
+            }
+        }
+    }
+}
+
+pub fn compose(inner: &[u8], outer: &[u8]) -> io::Result<Vec<u8>> {
+    let mut composer = Composer::new(inner)?;
+    composer.push(outer)?;
+    composer.encode()
+}
+
+#[test]
+fn chain() {
+    use std::io::{Cursor, Read};
+
+    let apply = |base: &[u8], delta: &[u8]| {
+        let mut output = Vec::new();
+        crate::Reader::new_strict(Cursor::new(base), delta).unwrap().read_to_end(&mut output).unwrap();
+        output
+    };
+
+    let mut versions = vec![Vec::new()];
+    for i in 0..200u32 {
+        versions[0].extend_from_slice(format!("line {} of the root\n", i * 7919 % 10007).as_bytes());
+    }
+    for step in 1..6 {
+        let mut next = versions[step - 1].clone();
+        next.drain(step * 300..step * 300 + 150);
+        next.splice(step * 500..step * 500, format!("version {} was here\n", step).bytes());
+        next.extend_from_slice(&versions[0][step * 100..step * 400]);
+        versions.push(next);
+    }
+
+    let deltas: Vec<_> = versions.windows(2).map(|w| crate::encode(&w[0], &w[1])).collect();
+    let mut composer = Composer::new(&deltas[0][..]).unwrap();
+    for (delta, version) in deltas[1..].iter().zip(&versions[2..]) {
+        composer.push(&delta[..]).unwrap();
+        assert!(apply(&versions[0], &composer.encode().unwrap()) == *version);
+    }
+    assert_eq!(composer.header(), Header { base_len: versions[0].len() as u64, result_len: versions[5].len() as u64 });
+
+    assert!(apply(b"hello", &compose(b"\x05\x06\x90\x05\x01!", b"\x06\x03\x91\x03\x03").unwrap()) == b"lo!");
+    assert_eq!(compose(b"\x05\x06\x90\x05\x01!", b"\x05\x03\x91\x03\x03").unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+    // inserts that nothing copies any more are dropped
+    let mut composer = Composer::new(&[&b"\x00\x64\x64"[..], &[b'a'; 100]].concat()[..]).unwrap();
+    composer.push(&b"\x64\x02\x91\x10\x01\x01!"[..]).unwrap();
+    assert_eq!(composer.data, b"a!");
+    assert_eq!(composer.segments, [Segment::Insert { start: 0, len: 2 }]);
+
+    // copies can't reach more than 4GiB into the base
+    let inner = b"\x80\x80\x84\x80\x10\x10\x9f\xff\xff\xff\xff\x10";
+    assert!(compose(inner, b"\x10\x02\x91\x00\x02").is_ok());
+    assert_eq!(compose(inner, b"\x10\x02\x91\x02\x02").unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,183 @@
+use std::cmp::min;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use crate::{Header, Command};
+
+pub const DEFAULT_WINDOW: usize = 16;
+
+// like git's HASH_LIMIT, this bounds the work spent on pathological inputs
+const BUCKET_LIMIT: usize = 64;
+const COPY_CHUNK: usize = 0x10000;
+
+pub struct DeltaIndex<'a> {
+    base: &'a [u8],
+    window: usize,
+    buckets: HashMap<u32, Vec<u32>>
+}
+
+impl<'a> DeltaIndex<'a> {
+    pub fn new(base: &'a [u8]) -> DeltaIndex<'a> {
+        DeltaIndex::with_window(base, DEFAULT_WINDOW)
+    }
+    pub fn with_window(base: &'a [u8], window: usize) -> DeltaIndex<'a> {
+        assert!(window > 0, "delta window must not be empty");
+        let mut buckets: HashMap<u32, Vec<u32>> = HashMap::new();
+        // copy offsets are 32 bits, so anything further into the base is out of reach
+        let reachable = min(base.len(), u32::MAX as usize);
+        let mut off = 0;
+        while off + window <= reachable {
+            let bucket = buckets.entry(RollingHash::new(&base[off..off + window]).value).or_default();
+            if bucket.len() < BUCKET_LIMIT {
+                bucket.push(off as u32);
+            }
+            off += window;
+        }
+        DeltaIndex { base, window, buckets }
+    }
+    pub fn base(&self) -> &'a [u8] {
+        self.base
+    }
+    pub fn encode(&self, target: &[u8], max_size: Option<usize>) -> Option<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut buf = [0; Header::MAX_LEN];
+        let header = Header { base_len: self.base.len() as u64, result_len: target.len() as u64 };
+        out.extend_from_slice(header.encode(&mut buf));
+
+        let w = self.window;
+        let mut insert_start = 0;
+        let mut i = 0;
+        let mut hash: Option<RollingHash> = None;
+        while i + w <= target.len() {
+            let h = hash.get_or_insert_with(|| RollingHash::new(&target[i..i + w]));
+            let (off, len) = self.longest_match(h.value, &target[i..]);
+            if len < w {
+                if i + w < target.len() {
+                    h.roll(target[i], target[i + w]);
+                }
+                i += 1;
+                continue;
+            }
+
+            // matches are found on block boundaries, so extend backwards into pending literals
+            let mut back = 0;
+            while i - back > insert_start && off > back && self.base[off - back - 1] == target[i - back - 1] {
+                back += 1;
+            }
+            emit_inserts(&mut out, &target[insert_start..i - back]);
+            // the index stops short of 4GiB into the base, so copies always fit
+            emit_copies(&mut out, (off - back) as u64, (len + back) as u64).ok()?;
+            i += len;
+            insert_start = i;
+            hash = None;
+
+            if max_size.is_some_and(|max| out.len() > max) {
+                return None;
+            }
+        }
+        emit_inserts(&mut out, &target[insert_start..]);
+
+        match max_size {
+            Some(max) if out.len() > max => None,
+            _ => Some(out)
+        }
+    }
+    fn longest_match(&self, hash: u32, target: &[u8]) -> (usize, usize) {
+        let mut best = (0, 0);
+        for &off in self.buckets.get(&hash).map_or(&[][..], |b| &b[..]) {
+            let off = off as usize;
+            let base = &self.base[off..min(self.base.len(), u32::MAX as usize)];
+            let len = base.iter().zip(target).take_while(|&(a, b)| a == b).count();
+            if len > best.1 {
+                best = (off, len);
+            }
+        }
+        best
+    }
+}
+
+pub fn encode(base: &[u8], target: &[u8]) -> Vec<u8> {
+    DeltaIndex::new(base).encode(target, None).unwrap()
+}
+
+pub(crate) fn emit_inserts(out: &mut Vec<u8>, data: &[u8]) {
+    let mut buf = [0; Command::MAX_LEN];
+    for chunk in data.chunks(Command::MAX_INSERT as usize) {
+        out.extend_from_slice(Command::Insert { len: chunk.len() as u8 }.encode(&mut buf));
+        out.extend_from_slice(chunk);
+    }
+}
+
+pub(crate) fn emit_copies(out: &mut Vec<u8>, mut off: u64, mut len: u64) -> io::Result<()> {
+    let mut buf = [0; Command::MAX_LEN];
+    while len > 0 {
+        let n = min(len, COPY_CHUNK as u64);
+        let copy_off = u32::try_from(off)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "copy offset is beyond 4GiB into the base"))?;
+        out.extend_from_slice(Command::Copy { off: copy_off, len: n as u32 }.encode(&mut buf));
+        off += n;
+        len -= n;
+    }
+    Ok(())
+}
+
+struct RollingHash {
+    value: u32,
+    // MULTIPLIER ** (window - 1), for dropping the outgoing byte
+    top: u32
+}
+
+impl RollingHash {
+    const MULTIPLIER: u32 = 0x0100_0193;
+
+    fn new(window: &[u8]) -> RollingHash {
+        let mut value = 0u32;
+        let mut top = 1u32;
+        for (i, &b) in window.iter().enumerate() {
+            value = value.wrapping_mul(RollingHash::MULTIPLIER).wrapping_add(b as u32 + 1);
+            if i != 0 {
+                top = top.wrapping_mul(RollingHash::MULTIPLIER);
+            }
+        }
+        RollingHash { value, top }
+    }
+    fn roll(&mut self, out: u8, input: u8) {
+        self.value = self.value
+            .wrapping_sub(self.top.wrapping_mul(out as u32 + 1))
+            .wrapping_mul(RollingHash::MULTIPLIER)
+            .wrapping_add(input as u32 + 1);
+    }
+}
+
+#[test]
+fn round_trip() {
+    use std::io::{Cursor, Read};
+
+    let mut base = Vec::new();
+    for i in 0..20_000u32 {
+        base.extend_from_slice(format!("line {} of the base\n", i * 7919 % 10007).as_bytes());
+    }
+    let mut target = base.clone();
+    target.drain(1000..1500);
+    target.splice(30_000..30_000, b"an insertion long enough to need several insert commands, well past the 127 byte limit of a single one. ".iter().cloned());
+    target.extend_from_slice(&base[..200_000]);
+    target.truncate(target.len() - 77);
+
+    let cases: [(&[u8], &[u8]); 5] = [
+        (&base, &target),
+        (&base, b""),
+        (b"", &target[..1000]),
+        (&base[..100], &base[50..100]),
+        (&target, &base)
+    ];
+    for &(base, target) in cases.iter() {
+        let delta = encode(base, target);
+        let mut output = Vec::new();
+        crate::Reader::new(Cursor::new(base), &delta[..]).unwrap().read_to_end(&mut output).unwrap();
+        assert!(output == target);
+        if target.len() > 1000 {
+            assert!(delta.len() < target.len() / 10);
+        }
+    }
+    assert_eq!(DeltaIndex::new(&base).encode(&target, Some(100)), None);
+}
@@ -1,39 +1,97 @@
+use std::cmp::min;
 use std::io::{self, BufRead, Read, Seek, SeekFrom};
-use crate::{Header, HeaderParser, Command, CommandParser};
+use failure::Fail;
+use io_at::ReadAt;
+use crate::{Header, HeaderParser, Command, CommandParser, InvalidDelta, Validator};
+
+pub(crate) struct Commands<Delta: BufRead> {
+    delta: Delta,
+    pub(crate) header: Header,
+    pub(crate) command: Command,
+    validator: Option<Validator>
+}
+
+impl<Delta: BufRead> Commands<Delta> {
+    pub(crate) fn new(mut delta: Delta) -> io::Result<Commands<Delta>> {
+        let header = gulp::from_reader(&mut delta, HeaderParser::default)?;
+        Ok(Commands { delta, header, command: Command::Insert { len: 0 }, validator: None })
+    }
+    pub(crate) fn strict(&mut self, base_len: u64) -> io::Result<()> {
+        let validator = Validator::new(self.header);
+        validator.base(base_len).map_err(invalid_data)?;
+        self.validator = Some(validator);
+        Ok(())
+    }
+    pub(crate) fn next(&mut self) -> io::Result<bool> {
+        if let Some(ref v) = self.validator {
+            if v.is_complete() {
+                if !self.delta.fill_buf()?.is_empty() {
+                    return Err(invalid_data(InvalidDelta::TrailingData));
+                }
+                return Ok(false);
+            }
+        }
+        match gulp::next_from_reader(&mut self.delta, CommandParser::default)? {
+            Some(c) => {
+                if let Some(ref mut v) = self.validator {
+                    v.command(c).map_err(invalid_data)?;
+                }
+                self.command = c;
+                Ok(true)
+            }
+            None => {
+                if let Some(ref v) = self.validator {
+                    v.finish().map_err(invalid_data)?;
+                }
+                Ok(false)
+            }
+        }
+    }
+    pub(crate) fn read_insert(&mut self, len: &mut u8, buf: &mut [u8]) -> io::Result<usize> {
+        let n = (&mut self.delta).take(*len as u64).read(buf)?;
+        *len -= n as u8;
+        Ok(n)
+    }
+    fn check_short(&self, n: usize, buf: &[u8]) -> io::Result<usize> {
+        if n == 0 && !buf.is_empty() && self.validator.is_some() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(n)
+    }
+}
 
 pub struct Reader<Base: Read + Seek, Delta: BufRead> {
     base: Base,
-    delta: Delta,
-    header: Header,
-    command: Command,
+    commands: Commands<Delta>,
     seek: bool
 }
 
 impl<Base: Read + Seek, Delta: BufRead> Reader<Base, Delta> {
-    pub fn new(base: Base, mut delta: Delta) -> io::Result<Reader<Base, Delta>> {
-        let header = gulp::from_reader(&mut delta, HeaderParser::default)?;
-        Ok(Reader { base, delta, header, command: Command::Insert { len: 0 }, seek: false })
+    pub fn new(base: Base, delta: Delta) -> io::Result<Reader<Base, Delta>> {
+        Ok(Reader { base, commands: Commands::new(delta)?, seek: false })
+    }
+    pub fn new_strict(base: Base, delta: Delta) -> io::Result<Reader<Base, Delta>> {
+        let mut reader = Reader::new(base, delta)?;
+        let base_len = reader.base.seek(SeekFrom::End(0))?;
+        reader.commands.strict(base_len)?;
+        Ok(reader)
     }
     pub fn header(&self) -> Header {
-        self.header
+        self.commands.header
     }
 }
 
 impl<Base: Read + Seek, Delta: BufRead> Read for Reader<Base, Delta> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if self.command.len() == 0 {
-            match gulp::next_from_reader(&mut self.delta, CommandParser::default)? {
-                Some(c) => { self.command = c; self.seek = true },
-                None => return Ok(0)
-            };
-        }
-        match self.command {
-            Command::Insert { ref mut len } => {
-                let mut r = (&mut self.delta).take(*len as u64);
-                let n = r.read(buf)?;
-                *len -= n as u8;
-                Ok(n)
+        if self.commands.command.len() == 0 {
+            if !self.commands.next()? {
+                return Ok(0);
             }
+            self.seek = true;
+        }
+        let mut command = self.commands.command;
+        let n = match command {
+            Command::Insert { ref mut len } => self.commands.read_insert(len, buf)?,
             Command::Copy { ref mut len, off } => {
                 if self.seek {
                     self.base.seek(SeekFrom::Start(off as u64))?;
@@ -42,14 +100,16 @@ impl<Base: Read + Seek, Delta: BufRead> Read for Reader<Base, Delta> {
                 let mut r = (&mut self.base).take(*len as u64);
                 let n = r.read(buf)?;
                 *len -= n as u32;
-                Ok(n)
+                n
             }
-        }
+        };
+        self.commands.command = command;
+        self.commands.check_short(n, buf)
     }
     #[cfg(feature = "nightly")]
     unsafe fn initializer(&self) -> io::Initializer {
         let base = self.base.initializer();
-        let delta = self.delta.initializer();
+        let delta = self.commands.delta.initializer();
         if base.should_initialize() || delta.should_initialize() {
             io::Initializer::zeroing()
         } else {
@@ -57,3 +117,75 @@ impl<Base: Read + Seek, Delta: BufRead> Read for Reader<Base, Delta> {
         }
     }
 }
+
+pub struct ReaderAt<Base: ReadAt, Delta: BufRead> {
+    base: Base,
+    commands: Commands<Delta>,
+    copied: u64
+}
+
+impl<Base: ReadAt, Delta: BufRead> ReaderAt<Base, Delta> {
+    pub fn new(base: Base, delta: Delta) -> io::Result<ReaderAt<Base, Delta>> {
+        Ok(ReaderAt { base, commands: Commands::new(delta)?, copied: 0 })
+    }
+    pub fn new_strict(base: Base, base_len: u64, delta: Delta) -> io::Result<ReaderAt<Base, Delta>> {
+        let mut reader = ReaderAt::new(base, delta)?;
+        reader.commands.strict(base_len)?;
+        Ok(reader)
+    }
+    pub fn header(&self) -> Header {
+        self.commands.header
+    }
+}
+
+impl<Base: ReadAt, Delta: BufRead> Read for ReaderAt<Base, Delta> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.commands.command.len() == 0 {
+            if !self.commands.next()? {
+                return Ok(0);
+            }
+            self.copied = 0;
+        }
+        let mut command = self.commands.command;
+        let n = match command {
+            Command::Insert { ref mut len } => self.commands.read_insert(len, buf)?,
+            Command::Copy { ref mut len, off } => {
+                let m = min(buf.len(), *len as usize);
+                let n = self.base.read_at(off as u64 + self.copied, &mut buf[..m])
+                    .map_err(|e| io::Error::other(e.compat()))?;
+                *len -= n as u32;
+                self.copied += n as u64;
+                n
+            }
+        };
+        self.commands.command = command;
+        self.commands.check_short(n, buf)
+    }
+}
+
+pub(crate) fn invalid_data(e: InvalidDelta) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.compat())
+}
+
+#[test]
+fn strict() {
+    use std::io::Cursor;
+
+    let apply = |base: &[u8], delta: &[u8]| -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        Reader::new_strict(Cursor::new(base), delta)?.read_to_end(&mut output)?;
+        let mut output_at = Vec::new();
+        ReaderAt::new_strict(base, base.len() as u64, delta)?.read_to_end(&mut output_at)?;
+        assert_eq!(output, output_at);
+        Ok(output)
+    };
+    let kind = |r: io::Result<Vec<u8>>| r.unwrap_err().kind();
+
+    assert_eq!(apply(b"hello", b"\x05\x06\x90\x05\x01!").unwrap(), b"hello!");
+    assert_eq!(kind(apply(b"hello!", b"\x05\x06\x90\x05\x01!")), io::ErrorKind::InvalidData);
+    assert_eq!(kind(apply(b"hello", b"\x05\x06\x91\x01\x05\x01!")), io::ErrorKind::InvalidData);
+    assert_eq!(kind(apply(b"hello", b"\x05\x07\x90\x05\x01!")), io::ErrorKind::InvalidData);
+    assert_eq!(kind(apply(b"hello", b"\x05\x05\x90\x05\x01!")), io::ErrorKind::InvalidData);
+    assert_eq!(kind(apply(b"hello", b"\x05\x06\x90\x05\x01!\x01?")), io::ErrorKind::InvalidData);
+    assert_eq!(kind(apply(b"hello", b"\x05\x06\x90\x05\x02!")), io::ErrorKind::InvalidData);
+}
@@ -6,7 +6,11 @@ use safe_shl::SafeShl;
 use gulp::{Parse, ParseResult};
 
 #[cfg(feature = "std")] pub use io::*;
+#[cfg(feature = "std")] pub use encode::*;
+#[cfg(feature = "std")] pub use compose::*;
 #[cfg(feature = "std")] mod io;
+#[cfg(feature = "std")] mod encode;
+#[cfg(feature = "std")] mod compose;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct Header {
@@ -14,6 +18,29 @@ pub struct Header {
     pub result_len: u64
 }
 
+impl Header {
+    pub const MAX_LEN: usize = 20;
+
+    pub fn encode<'a>(&self, buf: &'a mut [u8; Header::MAX_LEN]) -> &'a [u8] {
+        let n = encode_leb128(self.base_len, &mut buf[..]);
+        let m = encode_leb128(self.result_len, &mut buf[n..]);
+        &buf[..n + m]
+    }
+}
+
+fn encode_leb128(mut n: u64, buf: &mut [u8]) -> usize {
+    let mut i = 0;
+    loop {
+        buf[i] = (n & 0x7F) as u8;
+        n >>= 7;
+        i += 1;
+        if n == 0 {
+            return i;
+        }
+        buf[i - 1] |= 0x80;
+    }
+}
+
 #[derive(Copy, Clone, Debug, Fail, Eq, PartialEq)]
 #[fail(display = "invalid delta header")]
 pub struct InvalidHeader(());
@@ -40,18 +67,99 @@ pub enum Command {
 }
 
 impl Command {
+    pub const MAX_LEN: usize = 8;
+    pub const MAX_INSERT: u8 = 0x7F;
+    pub const MAX_COPY: u32 = 0xFF_FFFF;
+
     pub fn len(&self) -> u32 {
         match *self {
             Command::Insert { len, .. } => len as u32,
             Command::Copy   { len, .. } => len
         }
     }
+    pub fn encode<'a>(&self, buf: &'a mut [u8; Command::MAX_LEN]) -> &'a [u8] {
+        match *self {
+            Command::Insert { len } => {
+                debug_assert!(len != 0 && len <= Command::MAX_INSERT);
+                buf[0] = len;
+                &buf[..1]
+            }
+            Command::Copy { off, len } => {
+                debug_assert!(len != 0 && len <= Command::MAX_COPY);
+                // a zero length means 0x10000, so that one fits in no bytes at all
+                let len = if len == 0x10000 { 0 } else { len };
+                let mut op = 0x80;
+                let mut n = 1;
+                for i in 0..7 {
+                    let b = if i < 4 { (off >> (i * 8)) as u8 } else { (len >> ((i - 4) * 8)) as u8 };
+                    if b != 0 {
+                        op |= 1 << i;
+                        buf[n] = b;
+                        n += 1;
+                    }
+                }
+                buf[0] = op;
+                &buf[..n]
+            }
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, Fail, Eq, PartialEq)]
 #[fail(display = "invalid delta command")]
 pub struct InvalidCommand(());
 
+#[derive(Copy, Clone, Debug, Fail, Eq, PartialEq)]
+pub enum InvalidDelta {
+    #[fail(display = "delta base length mismatch")]
+    BaseLength,
+    #[fail(display = "delta copy out of range of base")]
+    CopyRange,
+    #[fail(display = "delta result length mismatch")]
+    ResultLength,
+    #[fail(display = "trailing data after delta")]
+    TrailingData
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Validator {
+    header: Header,
+    produced: u64
+}
+
+impl Validator {
+    pub fn new(header: Header) -> Validator {
+        Validator { header, produced: 0 }
+    }
+    pub fn base(&self, base_len: u64) -> Result<(), InvalidDelta> {
+        if base_len != self.header.base_len {
+            return Err(InvalidDelta::BaseLength);
+        }
+        Ok(())
+    }
+    pub fn command(&mut self, command: Command) -> Result<(), InvalidDelta> {
+        if let Command::Copy { off, len } = command {
+            if off as u64 + len as u64 > self.header.base_len {
+                return Err(InvalidDelta::CopyRange);
+            }
+        }
+        self.produced += command.len() as u64;
+        if self.produced > self.header.result_len {
+            return Err(InvalidDelta::ResultLength);
+        }
+        Ok(())
+    }
+    pub fn is_complete(&self) -> bool {
+        self.produced == self.header.result_len
+    }
+    pub fn finish(&self) -> Result<(), InvalidDelta> {
+        if !self.is_complete() {
+            return Err(InvalidDelta::ResultLength);
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub struct CommandParser(CommandParserState);
 
@@ -0,0 +1,2 @@
+#![no_main]
+gulp::split_fuzz!(git_pack::IndexHeaderParser);
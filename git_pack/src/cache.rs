@@ -0,0 +1,127 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64
+}
+
+// inflated delta bases keyed by pack offset, evicting the least recently used once over budget
+#[derive(Debug)]
+pub struct DeltaBaseCache {
+    budget: usize,
+    used: usize,
+    tick: u64,
+    entries: HashMap<u64, (git::ObjectKind, Arc<Vec<u8>>, u64)>,
+    lru: BTreeMap<u64, u64>,
+    stats: CacheStats
+}
+
+impl DeltaBaseCache {
+    // git's default core.deltaBaseCacheLimit
+    pub const DEFAULT_BUDGET: usize = 96 << 20;
+
+    pub fn new(budget: usize) -> DeltaBaseCache {
+        DeltaBaseCache {
+            budget,
+            used: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            stats: CacheStats::default()
+        }
+    }
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+    pub fn used(&self) -> usize {
+        self.used
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.evict(0);
+    }
+    pub fn get(&mut self, offset: u64) -> Option<(git::ObjectKind, Arc<Vec<u8>>)> {
+        let tick = self.tick;
+        match self.entries.get_mut(&offset) {
+            Some(&mut (kind, ref data, ref mut last)) => {
+                self.lru.remove(last);
+                self.lru.insert(tick, offset);
+                *last = tick;
+                self.tick += 1;
+                self.stats.hits += 1;
+                Some((kind, data.clone()))
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+    pub fn insert(&mut self, offset: u64, kind: git::ObjectKind, data: Arc<Vec<u8>>) {
+        self.remove(offset);
+        if data.len() > self.budget {
+            return;
+        }
+        self.evict(data.len());
+        self.used += data.len();
+        self.lru.insert(self.tick, offset);
+        self.entries.insert(offset, (kind, data, self.tick));
+        self.tick += 1;
+    }
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.lru.clear();
+        self.used = 0;
+    }
+    fn remove(&mut self, offset: u64) {
+        if let Some((_, data, last)) = self.entries.remove(&offset) {
+            self.lru.remove(&last);
+            self.used -= data.len();
+        }
+    }
+    // makes room for `len` more bytes
+    fn evict(&mut self, len: usize) {
+        while self.used + len > self.budget {
+            let offset = match self.lru.values().next() {
+                Some(&offset) => offset,
+                None => return
+            };
+            self.remove(offset);
+            self.stats.evictions += 1;
+        }
+    }
+}
+
+#[test]
+fn lru() {
+    let blob = git::ObjectKind::Blob;
+    let mut cache = DeltaBaseCache::new(10);
+    cache.insert(1, blob, Arc::new(vec![1; 4]));
+    cache.insert(2, blob, Arc::new(vec![2; 4]));
+    assert_eq!(cache.get(1).map(|(_, data)| data[0]), Some(1));
+    cache.insert(3, blob, Arc::new(vec![3; 4]));
+    assert!(cache.get(2).is_none());
+    assert_eq!(cache.get(3).map(|(_, data)| data[0]), Some(3));
+    assert_eq!(cache.used(), 8);
+
+    cache.insert(4, blob, Arc::new(vec![4; 11]));
+    assert!(cache.get(4).is_none());
+    assert_eq!(cache.len(), 2);
+
+    cache.set_budget(4);
+    assert!(cache.get(1).is_none());
+    assert!(cache.get(3).is_some());
+    assert_eq!(cache.stats(), CacheStats { hits: 3, misses: 3, evictions: 2 });
+}
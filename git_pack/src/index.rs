@@ -0,0 +1,573 @@
+use failure::Fail;
+use byteorder::{ByteOrder, NetworkEndian};
+use gulp::{Parse, ParseResult};
+use io_at::ReadAt;
+use crate::{ReadAtResult, parse_at, read_exact_at, trailer_at};
+
+#[cfg(feature = "std")] use std::{mem, io};
+
+#[derive(Copy, Clone, Debug, Fail, Eq, PartialEq)]
+pub enum InvalidIndex {
+    #[fail(display = "invalid pack index header")]
+    Header,
+    #[fail(display = "invalid pack index fanout table")]
+    Fanout,
+    #[fail(display = "pack index object ids out of order")]
+    Order,
+    #[fail(display = "invalid pack index offset")]
+    Offset,
+    #[fail(display = "pack index checksum mismatch")]
+    ChecksumMismatch,
+    #[fail(display = "pack index object ids use a different hash")]
+    Hash,
+    #[fail(display = "SHA-1 collision attack detected in pack index")]
+    Collision
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct IndexHeader {
+    pub hash: git::HashKind,
+    pub fanout: [u32; 256]
+}
+
+impl IndexHeader {
+    const LEN: u64 = 8 + 256 * 4;
+
+    pub fn count(&self) -> u32 {
+        self.fanout[255]
+    }
+    pub fn bucket(&self, first: u8) -> (u32, u32) {
+        let start = match first {
+            0 => 0,
+            n => self.fanout[n as usize - 1]
+        };
+        (start, self.fanout[first as usize])
+    }
+    fn object_id_offset(&self, i: u32) -> u64 {
+        IndexHeader::LEN + self.hash.digest_len() as u64 * i as u64
+    }
+    fn crc32_offset(&self, i: u32) -> u64 {
+        IndexHeader::LEN + self.hash.digest_len() as u64 * self.count() as u64 + 4 * i as u64
+    }
+    fn offset_offset(&self, i: u32) -> u64 {
+        IndexHeader::LEN + (self.hash.digest_len() as u64 + 4) * self.count() as u64 + 4 * i as u64
+    }
+    fn large_offset_offset(&self, i: u32) -> u64 {
+        IndexHeader::LEN + (self.hash.digest_len() as u64 + 8) * self.count() as u64 + 8 * i as u64
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct IndexHeaderParser(git::HashKind, IndexHeaderParserState);
+
+#[derive(Debug, Eq, PartialEq)]
+enum IndexHeaderParserState {
+    Tag(usize),
+    Fanout(usize, [u32; 256], gulp::Bytes<[u8; 4]>)
+}
+
+impl IndexHeaderParser {
+    pub fn new(hash: git::HashKind) -> IndexHeaderParser {
+        IndexHeaderParser(hash, IndexHeaderParserState::Tag(0))
+    }
+}
+
+impl Default for IndexHeaderParser {
+    fn default() -> IndexHeaderParser {
+        IndexHeaderParser::new(git::HashKind::Sha1)
+    }
+}
+
+impl Parse for IndexHeaderParser {
+    type Output = IndexHeader;
+    type Err = InvalidIndex;
+    fn parse(self, buf: &[u8]) -> ParseResult<Self> {
+        let hash = self.0;
+        match self.1 {
+            IndexHeaderParserState::Tag(n)               => IndexHeaderParser::parse_tag(hash, n, buf),
+            IndexHeaderParserState::Fanout(i, fanout, p) => IndexHeaderParser::parse_fanout(hash, i, fanout, p, buf)
+        }
+    }
+}
+
+impl IndexHeaderParser {
+    fn parse_tag(hash: git::HashKind, n: usize, buf: &[u8]) -> ParseResult<Self> {
+        const TAG: &[u8] = b"\xfftOc\x00\x00\x00\x02";
+        let len = core::cmp::min(TAG.len() - n, buf.len());
+        if buf[..len] != TAG[n..n + len] {
+            gulp::Result::Err(InvalidIndex::Header)
+        } else if n + len < TAG.len() {
+            gulp::Result::Incomplete(IndexHeaderParser(hash, IndexHeaderParserState::Tag(n + len)))
+        } else {
+            IndexHeaderParser::parse_fanout(hash, 0, [0; 256], gulp::Bytes::default(), &buf[len..])
+        }
+    }
+    fn parse_fanout(hash: git::HashKind, mut i: usize, mut fanout: [u32; 256], mut p: gulp::Bytes<[u8; 4]>, mut buf: &[u8]) -> ParseResult<Self> {
+        loop {
+            match p.parse(buf) {
+                gulp::Result::Incomplete(p) => return gulp::Result::Incomplete(IndexHeaderParser(hash, IndexHeaderParserState::Fanout(i, fanout, p))),
+                gulp::Result::Err(e) => match e {},
+                gulp::Result::Ok(n, tail) => {
+                    let n = NetworkEndian::read_u32(&n);
+                    if i > 0 && n < fanout[i - 1] {
+                        return gulp::Result::Err(InvalidIndex::Fanout);
+                    }
+                    fanout[i] = n;
+                    i += 1;
+                    if i == fanout.len() {
+                        return gulp::Result::Ok(IndexHeader { hash, fanout }, tail);
+                    }
+                    p = gulp::Bytes::default();
+                    buf = tail;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct IndexEntry {
+    pub id: git::ObjectId,
+    pub offset: u64,
+    pub crc32: u32
+}
+
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct Index {
+    header: IndexHeader,
+    entries: Vec<IndexEntry>,
+    by_offset: Vec<u32>,
+    pack_checksum: git::ObjectId,
+    checksum: git::ObjectId
+}
+
+#[cfg(feature = "std")]
+impl Index {
+    pub fn header(&self) -> &IndexHeader {
+        &self.header
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+    pub fn pack_checksum(&self) -> git::ObjectId {
+        self.pack_checksum
+    }
+    pub fn checksum(&self) -> git::ObjectId {
+        self.checksum
+    }
+    pub fn find(&self, id: git::ObjectId) -> Option<&IndexEntry> {
+        let (start, end) = self.header.bucket(id.as_bytes()[0]);
+        let bucket = &self.entries[start as usize..end as usize];
+        bucket.binary_search_by_key(&id, |e| e.id).ok().map(|i| &bucket[i])
+    }
+    pub fn resolve(&self, prefix: &git::Prefix) -> Result<&IndexEntry, git::PrefixError> {
+        prefix.resolve_by_key(&self.entries, |e| &e.id)
+    }
+    pub fn abbreviate(&self, id: &git::ObjectId, min_len: usize) -> git::Prefix {
+        git::Prefix::shortest_by_key(id, &self.entries, |e| &e.id, min_len)
+    }
+    pub fn iter_by_offset(&self) -> impl Iterator<Item=&IndexEntry> {
+        self.by_offset.iter().map(move |&i| &self.entries[i as usize])
+    }
+    pub fn from_entries(mut entries: Vec<IndexEntry>, pack_checksum: git::ObjectId) -> Result<Index, InvalidIndex> {
+        let hash = pack_checksum.hash();
+        if entries.iter().any(|e| e.id.hash() != hash) {
+            return Err(InvalidIndex::Hash);
+        }
+        entries.sort_by_key(|e| e.id);
+        if entries.windows(2).any(|w| w[0].id == w[1].id) {
+            return Err(InvalidIndex::Order);
+        }
+        if entries.len() > u32::MAX as usize {
+            return Err(InvalidIndex::Fanout);
+        }
+
+        let mut fanout = [0; 256];
+        for entry in &entries {
+            fanout[entry.id.as_bytes()[0] as usize] += 1;
+        }
+        for i in 1..fanout.len() {
+            fanout[i] += fanout[i - 1];
+        }
+
+        let by_offset = Index::sort_by_offset(&entries)?;
+        let mut index = Index {
+            header: IndexHeader { hash, fanout },
+            entries,
+            by_offset,
+            pack_checksum,
+            checksum: git::ObjectId::null(hash)
+        };
+
+        let mut hasher = git::Hasher::new(hash);
+        index.serialize(|buf| {
+            hasher.update(buf);
+            Ok(())
+        }).unwrap();
+        index.checksum = hasher.digest().map_err(|_| InvalidIndex::Collision)?;
+        Ok(index)
+    }
+    pub fn write_to<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+        self.serialize(|buf| writer.write_all(buf))?;
+        writer.write_all(self.checksum.as_bytes())
+    }
+    fn serialize<F: FnMut(&[u8]) -> io::Result<()>>(&self, mut write: F) -> io::Result<()> {
+        write(b"\xfftOc\x00\x00\x00\x02")?;
+        for &n in self.header.fanout.iter() {
+            write(&n.to_be_bytes())?;
+        }
+        for entry in &self.entries {
+            write(entry.id.as_bytes())?;
+        }
+        for entry in &self.entries {
+            write(&entry.crc32.to_be_bytes())?;
+        }
+        let mut large_offsets = 0u32;
+        for entry in &self.entries {
+            let offset = if entry.offset & !0x7FFF_FFFF == 0 {
+                entry.offset as u32
+            } else {
+                large_offsets += 1;
+                0x8000_0000 | (large_offsets - 1)
+            };
+            write(&offset.to_be_bytes())?;
+        }
+        for entry in &self.entries {
+            if entry.offset & !0x7FFF_FFFF != 0 {
+                write(&entry.offset.to_be_bytes())?;
+            }
+        }
+        write(self.pack_checksum.as_bytes())
+    }
+    fn sort_by_offset(entries: &[IndexEntry]) -> Result<Vec<u32>, InvalidIndex> {
+        let mut by_offset: Vec<u32> = (0..entries.len() as u32).collect();
+        by_offset.sort_by_key(|&i| entries[i as usize].offset);
+        if by_offset.windows(2).any(|w| entries[w[0] as usize].offset == entries[w[1] as usize].offset) {
+            return Err(InvalidIndex::Offset);
+        }
+        Ok(by_offset)
+    }
+}
+
+#[cfg(feature = "std")]
+pub struct IndexParser {
+    state: IndexParserState,
+    hasher: git::Hasher,
+    header: IndexHeader,
+    entries: Vec<IndexEntry>,
+    crc32s: usize,
+    offsets: usize,
+    large_offsets: Vec<u64>,
+    large_count: usize,
+    pack_checksum: Option<git::ObjectId>
+}
+
+#[cfg(feature = "std")]
+enum IndexParserState {
+    Header(IndexHeaderParser),
+    ObjectId(git::ObjectIdParser),
+    Crc32(gulp::Bytes<[u8; 4]>),
+    Offset(gulp::Bytes<[u8; 4]>),
+    LargeOffset(gulp::Bytes<[u8; 8]>),
+    PackChecksum(git::ObjectIdParser),
+    Checksum(git::ObjectIdParser),
+    Done
+}
+
+#[cfg(feature = "std")]
+impl IndexParser {
+    pub fn new(hash: git::HashKind) -> IndexParser {
+        IndexParser {
+            state: IndexParserState::Header(IndexHeaderParser::new(hash)),
+            hasher: git::Hasher::new(hash),
+            header: IndexHeader { hash, fanout: [0; 256] },
+            entries: Vec::new(),
+            crc32s: 0,
+            offsets: 0,
+            large_offsets: Vec::new(),
+            large_count: 0,
+            pack_checksum: None
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for IndexParser {
+    fn default() -> IndexParser {
+        IndexParser::new(git::HashKind::Sha1)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Parse for IndexParser {
+    type Output = Index;
+    type Err = InvalidIndex;
+    fn parse(mut self, mut buf: &[u8]) -> ParseResult<Self> {
+        loop {
+            let tail = match mem::replace(&mut self.state, IndexParserState::Done) {
+                IndexParserState::Header(p) => match self.step(p, buf, IndexParserState::Header) {
+                    Err(e) => return gulp::Result::Err(e),
+                    Ok(None) => return gulp::Result::Incomplete(self),
+                    Ok(Some((header, tail))) => {
+                        self.header = header;
+                        tail
+                    }
+                },
+                IndexParserState::ObjectId(p) => match self.step(p, buf, IndexParserState::ObjectId) {
+                    Err(e) => match e {},
+                    Ok(None) => return gulp::Result::Incomplete(self),
+                    Ok(Some((id, tail))) => {
+                        let i = self.entries.len() as u32;
+                        let (start, end) = self.header.bucket(id.as_bytes()[0]);
+                        if i < start || i >= end {
+                            return gulp::Result::Err(InvalidIndex::Fanout);
+                        }
+                        if self.entries.last().is_some_and(|e| e.id >= id) {
+                            return gulp::Result::Err(InvalidIndex::Order);
+                        }
+                        self.entries.push(IndexEntry { id, offset: 0, crc32: 0 });
+                        tail
+                    }
+                },
+                IndexParserState::Crc32(p) => match self.step(p, buf, IndexParserState::Crc32) {
+                    Err(e) => match e {},
+                    Ok(None) => return gulp::Result::Incomplete(self),
+                    Ok(Some((crc32, tail))) => {
+                        self.entries[self.crc32s].crc32 = NetworkEndian::read_u32(&crc32);
+                        self.crc32s += 1;
+                        tail
+                    }
+                },
+                IndexParserState::Offset(p) => match self.step(p, buf, IndexParserState::Offset) {
+                    Err(e) => match e {},
+                    Ok(None) => return gulp::Result::Incomplete(self),
+                    Ok(Some((offset, tail))) => {
+                        let offset = NetworkEndian::read_u32(&offset);
+                        if offset & 0x8000_0000 != 0 {
+                            self.large_count += 1;
+                        }
+                        self.entries[self.offsets].offset = offset as u64;
+                        self.offsets += 1;
+                        tail
+                    }
+                },
+                IndexParserState::LargeOffset(p) => match self.step(p, buf, IndexParserState::LargeOffset) {
+                    Err(e) => match e {},
+                    Ok(None) => return gulp::Result::Incomplete(self),
+                    Ok(Some((offset, tail))) => {
+                        self.large_offsets.push(NetworkEndian::read_u64(&offset));
+                        tail
+                    }
+                },
+                IndexParserState::PackChecksum(p) => match self.step(p, buf, IndexParserState::PackChecksum) {
+                    Err(e) => match e {},
+                    Ok(None) => return gulp::Result::Incomplete(self),
+                    Ok(Some((checksum, tail))) => {
+                        self.pack_checksum = Some(checksum);
+                        tail
+                    }
+                },
+                IndexParserState::Checksum(p) => match p.parse(buf) {
+                    gulp::Result::Incomplete(p) => {
+                        self.state = IndexParserState::Checksum(p);
+                        return gulp::Result::Incomplete(self);
+                    }
+                    gulp::Result::Err(e) => match e {},
+                    gulp::Result::Ok(checksum, tail) => return match self.finish(checksum) {
+                        Ok(index) => gulp::Result::Ok(index, tail),
+                        Err(e) => gulp::Result::Err(e)
+                    }
+                },
+                IndexParserState::Done => unreachable!()
+            };
+            self.state = self.next_state();
+            buf = tail;
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl IndexParser {
+    fn step<'a, P: Parse>(&mut self, p: P, buf: &'a [u8], incomplete: fn(P) -> IndexParserState) -> Result<Option<(P::Output, &'a [u8])>, P::Err> {
+        match p.parse(buf) {
+            gulp::Result::Incomplete(p) => {
+                self.hasher.update(buf);
+                self.state = incomplete(p);
+                Ok(None)
+            }
+            gulp::Result::Err(e) => Err(e),
+            gulp::Result::Ok(v, tail) => {
+                self.hasher.update(&buf[..buf.len() - tail.len()]);
+                Ok(Some((v, tail)))
+            }
+        }
+    }
+    fn next_state(&self) -> IndexParserState {
+        let count = self.header.count() as usize;
+        let hash = self.header.hash;
+        if self.entries.len() < count {
+            IndexParserState::ObjectId(git::ObjectIdParser::new(hash))
+        } else if self.crc32s < count {
+            IndexParserState::Crc32(gulp::Bytes::default())
+        } else if self.offsets < count {
+            IndexParserState::Offset(gulp::Bytes::default())
+        } else if self.large_offsets.len() < self.large_count {
+            IndexParserState::LargeOffset(gulp::Bytes::default())
+        } else if self.pack_checksum.is_none() {
+            IndexParserState::PackChecksum(git::ObjectIdParser::new(hash))
+        } else {
+            IndexParserState::Checksum(git::ObjectIdParser::new(hash))
+        }
+    }
+    fn finish(self, checksum: git::ObjectId) -> Result<Index, InvalidIndex> {
+        if self.hasher.digest().map_err(|_| InvalidIndex::Collision)? != checksum {
+            return Err(InvalidIndex::ChecksumMismatch);
+        }
+
+        let mut entries = self.entries;
+        for entry in &mut entries {
+            if entry.offset & 0x8000_0000 != 0 {
+                let i = (entry.offset & 0x7FFF_FFFF) as usize;
+                entry.offset = *self.large_offsets.get(i).ok_or(InvalidIndex::Offset)?;
+            }
+        }
+
+        let by_offset = Index::sort_by_offset(&entries)?;
+        Ok(Index {
+            header: self.header,
+            entries,
+            by_offset,
+            pack_checksum: self.pack_checksum.unwrap(),
+            checksum
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct IndexFile<R: ReadAt> {
+    reader: R,
+    header: IndexHeader
+}
+
+impl<R: ReadAt> IndexFile<R> {
+    pub fn open(reader: R) -> ReadAtResult<IndexFile<R>, R::Err, InvalidIndex> {
+        IndexFile::open_with_hash(git::HashKind::Sha1, reader)
+    }
+    pub fn open_with_hash(hash: git::HashKind, reader: R) -> ReadAtResult<IndexFile<R>, R::Err, InvalidIndex> {
+        let (header, _) = parse_at(&reader, 0, IndexHeaderParser::new(hash))?;
+        Ok(IndexFile { reader, header })
+    }
+    pub fn header(&self) -> &IndexHeader {
+        &self.header
+    }
+    pub fn len(&self) -> u32 {
+        self.header.count()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+    // the checksum of the pack this index belongs to, just before the index's own
+    pub fn pack_checksum(&self) -> ReadAtResult<git::ObjectId, R::Err, InvalidIndex> {
+        trailer_at(&self.reader, self.header.hash, 1)
+    }
+    pub fn object_id(&self, i: u32) -> ReadAtResult<git::ObjectId, R::Err, InvalidIndex> {
+        assert!(i < self.len(), "index entry out of range");
+        let mut buf = [0; 32];
+        let buf = &mut buf[..self.header.hash.digest_len()];
+        read_exact_at(&self.reader, self.header.object_id_offset(i), buf)?;
+        Ok(git::ObjectId::from_bytes(self.header.hash, buf).unwrap())
+    }
+    pub fn crc32(&self, i: u32) -> ReadAtResult<u32, R::Err, InvalidIndex> {
+        assert!(i < self.len(), "index entry out of range");
+        let mut crc32 = [0; 4];
+        read_exact_at(&self.reader, self.header.crc32_offset(i), &mut crc32)?;
+        Ok(NetworkEndian::read_u32(&crc32))
+    }
+    pub fn offset(&self, i: u32) -> ReadAtResult<u64, R::Err, InvalidIndex> {
+        assert!(i < self.len(), "index entry out of range");
+        let mut offset = [0; 4];
+        read_exact_at(&self.reader, self.header.offset_offset(i), &mut offset)?;
+        let offset = NetworkEndian::read_u32(&offset);
+        if offset & 0x8000_0000 == 0 {
+            return Ok(offset as u64);
+        }
+        let mut offset64 = [0; 8];
+        read_exact_at(&self.reader, self.header.large_offset_offset(offset & 0x7FFF_FFFF), &mut offset64)?;
+        Ok(NetworkEndian::read_u64(&offset64))
+    }
+    pub fn entry(&self, i: u32) -> ReadAtResult<IndexEntry, R::Err, InvalidIndex> {
+        Ok(IndexEntry {
+            id: self.object_id(i)?,
+            offset: self.offset(i)?,
+            crc32: self.crc32(i)?
+        })
+    }
+    pub fn find(&self, id: git::ObjectId) -> ReadAtResult<Option<u32>, R::Err, InvalidIndex> {
+        let (mut lo, mut hi) = self.header.bucket(id.as_bytes()[0]);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match self.object_id(mid)?.cmp(&id) {
+                core::cmp::Ordering::Less    => lo = mid + 1,
+                core::cmp::Ordering::Greater => hi = mid,
+                core::cmp::Ordering::Equal   => return Ok(Some(mid))
+            }
+        }
+        Ok(None)
+    }
+    #[cfg(feature = "std")]
+    pub fn entries_by_offset(&self) -> ReadAtResult<Vec<IndexEntry>, R::Err, InvalidIndex> {
+        let mut entries = (0..self.len()).map(|i| self.entry(i)).collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|e| e.offset);
+        Ok(entries)
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn index_round_trip() {
+    for &hash in &[git::HashKind::Sha1, git::HashKind::Sha256] {
+        let id = |first: u8| {
+            let mut id = [0x5A; 32];
+            id[0] = first;
+            git::ObjectId::from_bytes(hash, &id[..hash.digest_len()]).unwrap()
+        };
+        let entry = |first: u8, offset: u64, crc32: u32| IndexEntry { id: id(first), offset, crc32 };
+        let entries = vec![
+            entry(0xC0, 12, 0xDEADBEEF),
+            entry(0x00, 0x1_0000_0000, 1),
+            entry(0xFF, 0x7FFF_FFFF, 2),
+            entry(0x12, 0x8000_0000, 3)
+        ];
+        let pack_checksum = id(7);
+        let index = Index::from_entries(entries.clone(), pack_checksum).unwrap();
+        let mut buf = Vec::new();
+        index.write_to(&mut buf).unwrap();
+        assert_eq!(buf.len(), 8 + 1024 + 4 * (hash.digest_len() + 4 + 4) + 2 * 8 + hash.digest_len() * 2);
+
+        let parsed = gulp::from_reader(&buf[..], || IndexParser::new(hash)).unwrap();
+        assert_eq!(parsed.entries(), index.entries());
+        assert_eq!(parsed.pack_checksum(), pack_checksum);
+        assert_eq!(parsed.checksum(), index.checksum());
+        let offsets: Vec<u64> = parsed.iter_by_offset().map(|e| e.offset).collect();
+        assert_eq!(offsets, [12, 0x7FFF_FFFF, 0x8000_0000, 0x1_0000_0000]);
+
+        let file = IndexFile::open_with_hash(hash, &buf[..]).unwrap();
+        for e in &entries {
+            let i = file.find(e.id).unwrap().unwrap();
+            assert_eq!(file.entry(i).unwrap(), *e);
+        }
+        assert_eq!(file.find(id(0x5A)).unwrap(), None);
+    }
+    let mixed = vec![IndexEntry { id: git::ObjectId::Sha256([1; 32]), offset: 12, crc32: 0 }];
+    assert_eq!(Index::from_entries(mixed, git::ObjectId::Sha1([7; 20])).unwrap_err(), InvalidIndex::Hash);
+}
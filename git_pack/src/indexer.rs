@@ -0,0 +1,430 @@
+use std::{cmp, thread};
+use std::io::{self, Read, Write, Seek};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use failure::Fail;
+use flate2::bufread::ZlibDecoder;
+use io_at::ReadAt;
+use crate::{ChecksumReader, EntryReader, EntryHeader, DeltaBase, FileHeader, FileHeaderParser, Index, IndexEntry, ObjectDatabase, PackWriter};
+use crate::{inflate_at, invalid_data, verify_trailer, Cursor};
+
+// supplies the bases that a thin pack's reference deltas point outside of it at
+pub trait BaseResolver {
+    fn resolve_base(&self, id: &git::ObjectId) -> io::Result<Option<(git::ObjectKind, Vec<u8>)>>;
+}
+
+impl<F: Fn(&git::ObjectId) -> io::Result<Option<(git::ObjectKind, Vec<u8>)>>> BaseResolver for F {
+    fn resolve_base(&self, id: &git::ObjectId) -> io::Result<Option<(git::ObjectKind, Vec<u8>)>> {
+        self(id)
+    }
+}
+
+impl BaseResolver for ObjectDatabase {
+    fn resolve_base(&self, id: &git::ObjectId) -> io::Result<Option<(git::ObjectKind, Vec<u8>)>> {
+        let mut reader = match self.read(id)? {
+            Some(reader) => reader,
+            None => return Ok(None)
+        };
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Ok(Some((reader.header().kind, data)))
+    }
+}
+
+// for packs that have to be self-contained
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NoBases;
+
+impl BaseResolver for NoBases {
+    fn resolve_base(&self, _: &git::ObjectId) -> io::Result<Option<(git::ObjectKind, Vec<u8>)>> {
+        Ok(None)
+    }
+}
+
+// computes the index of a pack: one pass over the entries to checksum them and hash the whole objects,
+// then the deltas are resolved outwards from each base, so every base is only inflated once.
+// the trees of deltas under different bases don't depend on each other, so they're spread over threads
+pub struct Indexer<B = NoBases> {
+    hash: git::HashKind,
+    bases: B,
+    threads: usize
+}
+
+impl Indexer<NoBases> {
+    pub fn new(hash: git::HashKind) -> Indexer<NoBases> {
+        Indexer::with_bases(hash, NoBases)
+    }
+}
+
+impl<B: BaseResolver> Indexer<B> {
+    pub fn with_bases(hash: git::HashKind, bases: B) -> Indexer<B> {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        Indexer { hash, bases, threads }
+    }
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = cmp::max(threads, 1);
+    }
+    pub fn index<R: ReadAt + Sync + ?Sized>(&self, pack: &R) -> io::Result<IndexedPack> {
+        let (version, mut entries, checksum, end) = self.scan(pack)?;
+
+        let mut deltas = Deltas::default();
+        for (i, entry) in entries.iter().enumerate() {
+            if let EntryHeader::Delta(delta) = entry.header {
+                match delta.base {
+                    DeltaBase::Offset(off) => match entry.offset.checked_sub(off) {
+                        Some(base) if off != 0 => deltas.by_offset.entry(base).or_default().push(i),
+                        _ => return Err(invalid_data("delta base offset precedes start of pack"))
+                    },
+                    DeltaBase::Reference(id) => deltas.by_id.entry(id).or_default().push(i)
+                }
+            }
+        }
+
+        let mut trees = Vec::new();
+        for (i, entry) in entries.iter().enumerate() {
+            if let (EntryHeader::Object(header), Some(id)) = (entry.header, entry.id) {
+                let children = deltas.children(entry.offset, &id);
+                if !children.is_empty() {
+                    trees.push(Tree { kind: header.kind, root: Root::Entry(i), children });
+                }
+            }
+        }
+        let resolution = Resolution { hash: self.hash, pack, entries: &entries, deltas: Mutex::new(deltas) };
+        let mut resolved = resolution.resolve_trees(trees, self.threads)?;
+
+        // whatever reference deltas are left wait on a base from outside the pack, or on an object that
+        // only turns up once one of those is resolved. so like git, fetch the bases one at a time in pack
+        // order, resolving all the pack can after each one, and never fetch what the pack produced itself
+        let mut waiting: Vec<_> = resolution.deltas.lock().unwrap().by_id.iter()
+            .map(|(id, children)| (children.iter().map(|&i| entries[i].offset).min(), *id))
+            .collect();
+        waiting.sort();
+        let mut missing = Vec::new();
+        for (_, id) in waiting {
+            let children = match resolution.deltas.lock().unwrap().by_id.remove(&id) {
+                Some(children) => children,
+                None => continue
+            };
+            let (kind, data) = match self.bases.resolve_base(&id)? {
+                Some(base) => base,
+                None => {
+                    // a later base might still produce it
+                    resolution.deltas.lock().unwrap().by_id.insert(id, children);
+                    continue;
+                }
+            };
+            let mut hasher = git::ObjectHasher::with_hash(self.hash, git::ObjectHeader { kind, size: data.len() as u64 });
+            hasher.update(&data);
+            if hasher.digest()? != id {
+                return Err(invalid_data(format!("delta base {} doesn't match its id", id)));
+            }
+            let tree = Tree { kind, root: Root::External(data.clone()), children };
+            resolved.extend(resolution.resolve_trees(vec![tree], self.threads)?);
+            missing.push((id, kind, data));
+        }
+        if let Some(id) = resolution.deltas.lock().unwrap().by_id.keys().next() {
+            return Err(invalid_data(format!("can't find delta base {}", id)));
+        }
+
+        for (i, id) in resolved {
+            entries[i].id = Some(id);
+        }
+        let entries = entries.into_iter()
+            .map(|e| match e.id {
+                Some(id) => Ok(IndexEntry { id, offset: e.offset, crc32: e.crc32 }),
+                None => Err(invalid_data(format!("can't resolve delta at offset {}", e.offset)))
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(IndexedPack { hash: self.hash, version, entries, checksum, end, missing })
+    }
+    fn scan<R: ReadAt + ?Sized>(&self, pack: &R) -> io::Result<(u32, Vec<ScannedEntry>, git::ObjectId, u64)> {
+        let mut file = ChecksumReader::with_hash(self.hash, io::BufReader::new(Cursor { reader: pack, offset: 0 }));
+        let file_header = gulp::from_reader(&mut file, FileHeaderParser::default)?;
+        let offset = file.offset();
+        let mut reader = EntryReader::with_hash(self.hash, file, offset);
+
+        let mut entries = Vec::with_capacity(file_header.count as usize);
+        for _ in 0..file_header.count {
+            let header = reader.next_header()?;
+            let body = reader.offset();
+            let (size, id) = match header {
+                EntryHeader::Object(header) => {
+                    let mut hasher = git::ObjectHasher::with_hash(self.hash, header);
+                    let size = io::copy(&mut ZlibDecoder::new(&mut reader), &mut hasher)?;
+                    (size, Some(hasher.digest()?))
+                }
+                EntryHeader::Delta(_) => (io::copy(&mut ZlibDecoder::new(&mut reader), &mut io::sink())?, None)
+            };
+            if size != header.size() {
+                return Err(invalid_data("entry size doesn't match its header"));
+            }
+            let span = reader.span();
+            entries.push(ScannedEntry { offset: span.offset, body, crc32: span.crc32, header, id });
+        }
+
+        let end = reader.offset();
+        let checksum = verify_trailer(reader.into_inner())?;
+        Ok((file_header.version, entries, checksum, end))
+    }
+}
+
+// a base along with the deltas against it
+struct Tree {
+    kind: git::ObjectKind,
+    root: Root,
+    children: Vec<usize>
+}
+
+enum Root {
+    Entry(usize),
+    External(Vec<u8>)
+}
+
+// what the threads resolving deltas share
+struct Resolution<'a, R: ?Sized> {
+    hash: git::HashKind,
+    pack: &'a R,
+    entries: &'a [ScannedEntry],
+    deltas: Mutex<Deltas>
+}
+
+impl<R: ReadAt + Sync + ?Sized> Resolution<'_, R> {
+    // returns the id of every delta in the trees, by entry
+    fn resolve_trees(&self, mut trees: Vec<Tree>, threads: usize) -> io::Result<Vec<(usize, git::ObjectId)>> {
+        let threads = cmp::min(threads, trees.len());
+        let mut resolved = Vec::new();
+        if threads <= 1 {
+            for tree in trees {
+                self.resolve(tree, &mut resolved)?;
+            }
+            return Ok(resolved);
+        }
+
+        // hand the trees out in pack order
+        trees.reverse();
+        let queue = Mutex::new(trees);
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads).map(|_| scope.spawn(|| {
+                let mut resolved = Vec::new();
+                loop {
+                    let tree = match queue.lock().unwrap().pop() {
+                        Some(tree) => tree,
+                        None => return Ok(resolved)
+                    };
+                    if let Err(e) = self.resolve(tree, &mut resolved) {
+                        // the pack is bad, so there's no point in the others carrying on
+                        queue.lock().unwrap().clear();
+                        return Err(e);
+                    }
+                }
+            })).collect();
+            for worker in workers {
+                resolved.extend(worker.join().unwrap()?);
+            }
+            Ok(resolved)
+        })
+    }
+    // applies the deltas against the root, then the deltas against their results, and so on
+    fn resolve(&self, tree: Tree, resolved: &mut Vec<(usize, git::ObjectId)>) -> io::Result<()> {
+        let Tree { kind, root, children } = tree;
+        let data = match root {
+            Root::Entry(i) => {
+                let entry = &self.entries[i];
+                let mut data = Vec::with_capacity(entry.header.size() as usize);
+                inflate_at(self.pack, entry.body).take(entry.header.size()).read_to_end(&mut data)?;
+                data
+            }
+            Root::External(data) => data
+        };
+        let mut stack = vec![(data, children)];
+        let mut delta = Vec::new();
+        while let Some((base, children)) = stack.last_mut() {
+            let i = match children.pop() {
+                Some(i) => i,
+                None => {
+                    stack.pop();
+                    continue;
+                }
+            };
+            let entry = &self.entries[i];
+            delta.clear();
+            inflate_at(self.pack, entry.body).take(entry.header.size()).read_to_end(&mut delta)?;
+            let mut output = Vec::new();
+            git_delta::ReaderAt::new_strict(&base[..], base.len() as u64, &delta[..])?.read_to_end(&mut output)?;
+
+            let mut hasher = git::ObjectHasher::with_hash(self.hash, git::ObjectHeader { kind, size: output.len() as u64 });
+            hasher.update(&output);
+            let id = hasher.digest()?;
+            resolved.push((i, id));
+
+            let children = self.deltas.lock().unwrap().children(entry.offset, &id);
+            if !children.is_empty() {
+                stack.push((output, children));
+            }
+        }
+        Ok(())
+    }
+}
+
+struct ScannedEntry {
+    offset: u64,
+    body: u64,
+    crc32: u32,
+    header: EntryHeader,
+    id: Option<git::ObjectId>
+}
+
+// the deltas still waiting on their base, by how they refer to it
+#[derive(Default)]
+struct Deltas {
+    by_offset: HashMap<u64, Vec<usize>>,
+    by_id: BTreeMap<git::ObjectId, Vec<usize>>
+}
+
+impl Deltas {
+    fn children(&mut self, offset: u64, id: &git::ObjectId) -> Vec<usize> {
+        let mut children = self.by_offset.remove(&offset).unwrap_or_default();
+        children.extend(self.by_id.remove(id).unwrap_or_default());
+        children
+    }
+}
+
+pub struct IndexedPack {
+    hash: git::HashKind,
+    version: u32,
+    entries: Vec<IndexEntry>,
+    checksum: git::ObjectId,
+    end: u64,
+    missing: Vec<(git::ObjectId, git::ObjectKind, Vec<u8>)>
+}
+
+impl IndexedPack {
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+    pub fn checksum(&self) -> git::ObjectId {
+        self.checksum
+    }
+    // the bases from outside the pack that its deltas need
+    pub fn missing(&self) -> impl Iterator<Item=&git::ObjectId> {
+        self.missing.iter().map(|(id, _, _)| id)
+    }
+    pub fn is_thin(&self) -> bool {
+        !self.missing.is_empty()
+    }
+    pub fn into_index(self) -> io::Result<Index> {
+        if self.is_thin() {
+            return Err(invalid_data(format!("pack is thin, and missing {} delta bases", self.missing.len())));
+        }
+        Index::from_entries(self.entries, self.checksum).map_err(|e| invalid_data(e.compat()))
+    }
+    // appends the missing bases to the pack as whole objects, then rewrites its header and checksum
+    pub fn fix_thin<W: Read + Write + Seek>(mut self, pack: W) -> io::Result<(W, Index)> {
+        if !self.is_thin() {
+            return Ok((pack, self.into_index()?));
+        }
+        let header = FileHeader { version: self.version, count: self.entries.len() as u32 };
+        let mut writer = PackWriter::append(self.hash, pack, header, self.end)?;
+        for (id, kind, data) in self.missing.drain(..) {
+            let span = writer.write_object(kind, &data)?;
+            self.entries.push(IndexEntry { id, offset: span.offset, crc32: span.crc32 });
+        }
+        let (pack, checksum) = writer.fixup()?;
+        self.checksum = checksum;
+        Ok((pack, self.into_index()?))
+    }
+}
+
+#[test]
+fn thin_pack() {
+    use crate::{IndexFile, Pack};
+    use crate::test_pack::{blob, Entry, TestPack};
+
+    let external = &b"hello, world"[..];
+    let objects: [&[u8]; 4] = [b"goodbye, world", b"goodbye, world!", b"goodbye, world!?", b"hello, world!"];
+    let test_pack = TestPack::new(&[
+        Entry::Whole(objects[0]),
+        Entry::Offset(0, objects[1]),
+        Entry::Reference(objects[1], objects[2]),
+        Entry::Reference(external, objects[3])
+    ]);
+    let pack = test_pack.data;
+
+    assert_eq!(Indexer::new(git::HashKind::Sha1).index(&pack[..]).err().unwrap().kind(), io::ErrorKind::InvalidData);
+
+    let bases = |id: &git::ObjectId| Ok(if *id == blob(external) { Some((git::ObjectKind::Blob, external.to_vec())) } else { None });
+    let indexed = Indexer::with_bases(git::HashKind::Sha1, bases).index(&pack[..]).unwrap();
+    assert_eq!(indexed.entries(), &test_pack.entries[..]);
+    assert_eq!(indexed.missing().collect::<Vec<_>>(), [&blob(external)]);
+
+    let (fixed, index) = indexed.fix_thin(io::Cursor::new(pack.clone())).unwrap();
+    let fixed = fixed.into_inner();
+    let reindexed = Indexer::new(git::HashKind::Sha1).index(&fixed[..]).unwrap();
+    assert!(!reindexed.is_thin());
+    assert_eq!(reindexed.checksum(), index.pack_checksum());
+    let reindexed = reindexed.into_index().unwrap();
+    assert_eq!(reindexed.entries(), index.entries());
+    assert_eq!(reindexed.len(), 5);
+
+    // a version 3 pack stays version 3
+    let mut v3 = pack.clone();
+    v3[7] = 3;
+    v3.truncate(v3.len() - 20);
+    let mut hasher = git::Hasher::new(git::HashKind::Sha1);
+    hasher.update(&v3);
+    v3.extend_from_slice(hasher.digest().unwrap().as_bytes());
+    let indexed = Indexer::with_bases(git::HashKind::Sha1, bases).index(&v3[..]).unwrap();
+    let (fixed_v3, _) = indexed.fix_thin(io::Cursor::new(v3)).unwrap();
+    let fixed_v3 = fixed_v3.into_inner();
+    assert_eq!(&fixed_v3[..8], b"PACK\0\0\0\x03");
+    assert_eq!(fixed_v3[8..fixed.len() - 20], fixed[8..fixed.len() - 20]);
+    assert!(!Indexer::new(git::HashKind::Sha1).index(&fixed_v3[..]).unwrap().is_thin());
+
+    let mut buf = Vec::new();
+    index.write_to(&mut buf).unwrap();
+    let pack = Pack::with_index(&fixed[..], IndexFile::open(&buf[..]).unwrap()).unwrap();
+    for &data in objects.iter().chain(Some(&external)) {
+        let offset = pack.find(&blob(data)).unwrap().unwrap();
+        assert_eq!(pack.read(offset).unwrap().1, data);
+    }
+
+    // a base that looks external but that another delta in the pack produces isn't fetched,
+    // even when the resolver could supply it
+    let test_pack = TestPack::new(&[Entry::Reference(external, objects[3]), Entry::Reference(objects[3], objects[2])]);
+    let bases = |id: &git::ObjectId| Ok([external, objects[3]].iter().find(|&&data| blob(data) == *id).map(|data| (git::ObjectKind::Blob, data.to_vec())));
+    let indexed = Indexer::with_bases(git::HashKind::Sha1, bases).index(&test_pack.data[..]).unwrap();
+    assert_eq!(indexed.entries(), &test_pack.entries[..]);
+    assert_eq!(indexed.missing().collect::<Vec<_>>(), [&blob(external)]);
+    let (fixed, index) = indexed.fix_thin(io::Cursor::new(test_pack.data)).unwrap();
+    assert_eq!(index.len(), 3);
+    assert!(!Indexer::new(git::HashKind::Sha1).index(&fixed.into_inner()[..]).unwrap().is_thin());
+}
+
+#[test]
+fn parallel_resolution() {
+    use crate::test_pack::{Entry, TestPack};
+
+    let (chains, depth) = (12, 6);
+    let object = |c: usize, v: usize| format!("chain {} version {}\n", c, v).repeat(v + 1).into_bytes();
+    let objects: Vec<_> = (0..depth).flat_map(|v| (0..chains).map(move |c| object(c, v))).collect();
+    let entries: Vec<_> = objects.iter().enumerate().map(|(i, data)| {
+        let (v, c) = (i / chains, i % chains);
+        if v == 0 {
+            Entry::Whole(data)
+        } else if (c + v) % 2 == 0 {
+            Entry::Offset(i - chains, data)
+        } else {
+            Entry::Reference(&objects[i - chains], data)
+        }
+    }).collect();
+    let test_pack = TestPack::new(&entries);
+
+    for &threads in &[1, 4] {
+        let mut indexer = Indexer::new(git::HashKind::Sha1);
+        indexer.set_threads(threads);
+        assert_eq!(indexer.index(&test_pack.data[..]).unwrap().entries(), &test_pack.entries[..]);
+    }
+}
@@ -0,0 +1,335 @@
+use std::io::{self, Read, Write, BufRead, Seek, SeekFrom};
+use flate2::{Compression, write::ZlibEncoder};
+use gulp::{IoError, IoResult};
+use crate::{FileHeader, TrailerParser, InvalidTrailer, EntryHeader, EntryHeaderParser, InvalidEntryHeader, EntrySpan};
+use crate::{DeltaHeader, DeltaBase};
+
+trait Update {
+    fn update(&mut self, buf: &[u8]);
+}
+
+impl Update for git::Hasher {
+    fn update(&mut self, buf: &[u8]) {
+        git::Hasher::update(self, buf)
+    }
+}
+
+impl Update for crc32fast::Hasher {
+    fn update(&mut self, buf: &[u8]) {
+        crc32fast::Hasher::update(self, buf)
+    }
+}
+
+// bytes are hashed the first time the read frontier passes over them,
+// so seeking back to re-read earlier entries doesn't disturb the digest
+struct Frontier<H> {
+    hasher: H,
+    offset: u64,
+    hashed: u64
+}
+
+impl<H: Update> Frontier<H> {
+    fn new(hasher: H, offset: u64) -> Frontier<H> {
+        Frontier { hasher, offset, hashed: offset }
+    }
+    fn advance(&mut self, buf: &[u8]) {
+        let end = self.offset + buf.len() as u64;
+        if self.offset <= self.hashed && self.hashed < end {
+            self.hasher.update(&buf[(self.hashed - self.offset) as usize..]);
+            self.hashed = end;
+        }
+        self.offset = end;
+    }
+}
+
+macro_rules! frontier_reader {
+    ($reader:ident) => {
+        impl<R: Read> Read for $reader<R> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let n = self.reader.read(buf)?;
+                self.frontier.advance(&buf[..n]);
+                Ok(n)
+            }
+        }
+
+        impl<R: BufRead> BufRead for $reader<R> {
+            fn fill_buf(&mut self) -> io::Result<&[u8]> {
+                self.reader.fill_buf()
+            }
+            fn consume(&mut self, amt: usize) {
+                // the consumed bytes are still buffered, so this doesn't touch the underlying reader
+                match self.reader.fill_buf() {
+                    Ok(buf) => self.frontier.advance(&buf[..amt]),
+                    Err(_) => self.frontier.offset += amt as u64
+                }
+                self.reader.consume(amt);
+            }
+        }
+
+        impl<R: Seek> Seek for $reader<R> {
+            fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+                self.frontier.offset = self.reader.seek(pos)?;
+                Ok(self.frontier.offset)
+            }
+        }
+    }
+}
+
+pub struct ChecksumReader<R> {
+    reader: R,
+    frontier: Frontier<git::Hasher>
+}
+
+impl<R> ChecksumReader<R> {
+    pub fn new(reader: R) -> ChecksumReader<R> {
+        ChecksumReader::with_hash(git::HashKind::Sha1, reader)
+    }
+    pub fn with_hash(hash: git::HashKind, reader: R) -> ChecksumReader<R> {
+        ChecksumReader { reader, frontier: Frontier::new(git::Hasher::new(hash), 0) }
+    }
+    pub fn hash(&self) -> git::HashKind {
+        self.frontier.hasher.hash()
+    }
+    pub fn offset(&self) -> u64 {
+        self.frontier.offset
+    }
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+    pub fn finish(self) -> (Result<git::ObjectId, git::Collision>, R) {
+        (self.frontier.hasher.digest(), self.reader)
+    }
+}
+
+frontier_reader!(ChecksumReader);
+
+pub fn verify_trailer<R: BufRead>(reader: ChecksumReader<R>) -> IoResult<git::ObjectId, InvalidTrailer> {
+    let (checksum, mut reader) = reader.finish();
+    let checksum = checksum.map_err(|_| IoError::Parse(InvalidTrailer::Collision))?;
+    let checksum = gulp::from_reader(&mut reader, || TrailerParser::new(checksum))?;
+    if !reader.fill_buf().map_err(IoError::Io)?.is_empty() {
+        return Err(IoError::Parse(InvalidTrailer::TrailingData));
+    }
+    Ok(checksum)
+}
+
+pub struct EntryReader<R> {
+    reader: R,
+    hash: git::HashKind,
+    start: u64,
+    frontier: Frontier<crc32fast::Hasher>
+}
+
+impl<R> EntryReader<R> {
+    pub fn new(reader: R, offset: u64) -> EntryReader<R> {
+        EntryReader::with_hash(git::HashKind::Sha1, reader, offset)
+    }
+    pub fn with_hash(hash: git::HashKind, reader: R, offset: u64) -> EntryReader<R> {
+        EntryReader { reader, hash, start: offset, frontier: Frontier::new(crc32fast::Hasher::new(), offset) }
+    }
+    pub fn offset(&self) -> u64 {
+        self.frontier.offset
+    }
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+    pub fn span(&self) -> EntrySpan {
+        EntrySpan {
+            offset: self.start,
+            len: self.frontier.hashed - self.start,
+            crc32: self.frontier.hasher.clone().finalize()
+        }
+    }
+}
+
+impl<R: BufRead> EntryReader<R> {
+    pub fn next_header(&mut self) -> IoResult<EntryHeader, InvalidEntryHeader> {
+        self.start = self.frontier.offset;
+        self.frontier = Frontier::new(crc32fast::Hasher::new(), self.start);
+        let hash = self.hash;
+        gulp::from_reader(self, || EntryHeaderParser::new(hash))
+    }
+}
+
+frontier_reader!(EntryReader);
+
+pub struct PackWriter<W> {
+    writer: W,
+    hasher: git::Hasher,
+    compression: Compression,
+    version: u32,
+    offset: u64,
+    count: u32,
+    expected: u32
+}
+
+impl<W: Write> PackWriter<W> {
+    pub fn new(writer: W, count: u32) -> io::Result<PackWriter<W>> {
+        PackWriter::with_hash(git::HashKind::Sha1, writer, count)
+    }
+    pub fn with_hash(hash: git::HashKind, mut writer: W, count: u32) -> io::Result<PackWriter<W>> {
+        let header = FileHeader::new(count);
+        let encoded = header.encode();
+        writer.write_all(&encoded)?;
+        let mut hasher = git::Hasher::new(hash);
+        hasher.update(&encoded);
+        Ok(PackWriter {
+            writer,
+            hasher,
+            compression: Compression::default(),
+            version: header.version,
+            offset: encoded.len() as u64,
+            count: 0,
+            expected: count
+        })
+    }
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+    pub fn write_object(&mut self, kind: git::ObjectKind, data: &[u8]) -> io::Result<EntrySpan> {
+        let header = git::ObjectHeader { kind, size: data.len() as u64 };
+        self.write_entry(From::from(header), data)
+    }
+    pub fn write_ofs_delta(&mut self, base_offset: u64, delta: &[u8]) -> io::Result<EntrySpan> {
+        let off = match self.offset.checked_sub(base_offset) {
+            Some(off) if off != 0 => off,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "delta base must precede the delta"))
+        };
+        let header = DeltaHeader { delta_len: delta.len() as u64, base: DeltaBase::Offset(off) };
+        self.write_entry(From::from(header), delta)
+    }
+    pub fn write_ref_delta(&mut self, base: git::ObjectId, delta: &[u8]) -> io::Result<EntrySpan> {
+        let header = DeltaHeader { delta_len: delta.len() as u64, base: DeltaBase::Reference(base) };
+        self.write_entry(From::from(header), delta)
+    }
+    pub fn write_entry(&mut self, header: EntryHeader, data: &[u8]) -> io::Result<EntrySpan> {
+        if header.size() != data.len() as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "entry size doesn't match its data"));
+        }
+        if let EntryHeader::Delta(DeltaHeader { base: DeltaBase::Reference(id), .. }) = header {
+            if id.hash() != self.hasher.hash() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "delta base uses a different hash than the pack"));
+            }
+        }
+        if self.count == u32::MAX {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "too many entries for one pack"));
+        }
+        let offset = self.offset;
+        let compression = self.compression;
+        let mut entry = EntryWriter { pack: self, crc: crc32fast::Hasher::new() };
+        let mut buf = [0; EntryHeader::MAX_LEN];
+        entry.write_all(header.encode(&mut buf))?;
+        let mut encoder = ZlibEncoder::new(&mut entry, compression);
+        encoder.write_all(data)?;
+        encoder.finish()?;
+        let crc32 = entry.crc.finalize();
+        self.count += 1;
+        Ok(EntrySpan { offset, len: self.offset - offset, crc32 })
+    }
+    pub fn finish(mut self) -> io::Result<(W, git::ObjectId)> {
+        if self.count != self.expected {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "entry count doesn't match the pack header"));
+        }
+        let checksum = self.hasher.digest()?;
+        self.writer.write_all(checksum.as_bytes())?;
+        Ok((self.writer, checksum))
+    }
+}
+
+impl<W: Read + Write + Seek> PackWriter<W> {
+    // appends entries to an existing pack, overwriting its checksum.
+    // the header and checksum are only right again after `fixup`, which keeps the pack's version.
+    pub(crate) fn append(hash: git::HashKind, mut writer: W, header: FileHeader, offset: u64) -> io::Result<PackWriter<W>> {
+        writer.seek(SeekFrom::Start(offset))?;
+        let count = header.count;
+        Ok(PackWriter {
+            writer,
+            hasher: git::Hasher::new(hash),
+            compression: Compression::default(),
+            version: header.version,
+            offset,
+            count,
+            expected: count
+        })
+    }
+    pub fn fixup(mut self) -> io::Result<(W, git::ObjectId)> {
+        let header = FileHeader { version: self.version, count: self.count }.encode();
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(&header)?;
+
+        let mut hasher = git::Hasher::new(self.hasher.hash());
+        hasher.update(&header);
+        let mut rest = (&mut self.writer).take(self.offset - header.len() as u64);
+        let mut buf = [0; 8192];
+        loop {
+            match rest.read(&mut buf)? {
+                0 => break,
+                n => hasher.update(&buf[..n])
+            }
+        }
+        if rest.limit() != 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "pack shrank while fixing up its header"));
+        }
+
+        let checksum = hasher.digest()?;
+        self.writer.write_all(checksum.as_bytes())?;
+        Ok((self.writer, checksum))
+    }
+}
+
+pub(crate) fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+struct EntryWriter<'a, W> {
+    pack: &'a mut PackWriter<W>,
+    crc: crc32fast::Hasher
+}
+
+impl<W: Write> Write for EntryWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.pack.writer.write(buf)?;
+        self.pack.hasher.update(&buf[..n]);
+        self.crc.update(&buf[..n]);
+        self.pack.offset += n as u64;
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.pack.writer.flush()
+    }
+}
+
+#[test]
+fn pack_round_trip() {
+    let mut writer = PackWriter::new(io::Cursor::new(Vec::new()), 0).unwrap();
+    let spans = [
+        writer.write_object(git::ObjectKind::Blob, b"hello, world").unwrap(),
+        writer.write_ofs_delta(12, b"\x0c\x0d\x90\x0c\x01!").unwrap(),
+        writer.write_ref_delta(git::ObjectId::Sha1([0x5A; 20]), b"\x0c\x01\x01?").unwrap()
+    ];
+    let (pack, checksum) = writer.fixup().unwrap();
+    let pack = pack.into_inner();
+
+    let mut reader = ChecksumReader::new(&pack[..]);
+    assert_eq!(gulp::from_reader(&mut reader, crate::FileHeaderParser::default).unwrap(), FileHeader::new(3));
+    let offset = reader.offset();
+    let mut reader = EntryReader::new(reader, offset);
+    let mut entries = Vec::new();
+    for span in &spans {
+        let header = reader.next_header().unwrap();
+        let mut body = Vec::new();
+        flate2::bufread::ZlibDecoder::new(&mut reader).read_to_end(&mut body).unwrap();
+        assert_eq!(reader.span(), *span);
+        entries.push((header, body));
+    }
+    assert_eq!(entries[0], (From::from(git::ObjectHeader { kind: git::ObjectKind::Blob, size: 12 }), b"hello, world".to_vec()));
+    assert_eq!(entries[1].0, From::from(DeltaHeader { delta_len: 6, base: DeltaBase::Offset(spans[1].offset - 12) }));
+    assert_eq!(entries[2].0, From::from(DeltaHeader { delta_len: 4, base: DeltaBase::Reference(git::ObjectId::Sha1([0x5A; 20])) }));
+    assert_eq!(verify_trailer(reader.into_inner()).unwrap(), checksum);
+}
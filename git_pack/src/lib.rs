@@ -1,31 +1,71 @@
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+use core::cmp;
 use failure::Fail;
 use safe_shl::SafeShl;
 use gulp::{Parse, ParseResult};
 
+pub use index::*;
+pub use read_at::*;
+#[cfg(feature = "std")] pub use io::*;
+#[cfg(feature = "std")] pub use cache::*;
+#[cfg(feature = "std")] pub use indexer::*;
+#[cfg(feature = "std")] pub use odb::*;
+#[cfg(feature = "std")] pub use pack::*;
+
+mod index;
+mod read_at;
+#[cfg(feature = "std")] mod io;
+#[cfg(feature = "std")] mod cache;
+#[cfg(feature = "std")] mod indexer;
+#[cfg(feature = "std")] mod odb;
+#[cfg(feature = "std")] mod pack;
+#[cfg(all(test, feature = "std"))] mod test_pack;
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct FileHeader {
+    pub version: u32,
     pub count: u32
 }
 
+impl FileHeader {
+    // git writes version 2, but still reads version 3
+    pub const VERSIONS: &'static [u32] = &[2, 3];
+
+    pub fn new(count: u32) -> FileHeader {
+        FileHeader { version: 2, count }
+    }
+    pub fn encode(&self) -> [u8; 12] {
+        use byteorder::ByteOrder;
+        let mut buf = *b"PACK\x00\x00\x00\x00\x00\x00\x00\x00";
+        byteorder::NetworkEndian::write_u32(&mut buf[4..8], self.version);
+        byteorder::NetworkEndian::write_u32(&mut buf[8..], self.count);
+        buf
+    }
+}
+
 #[derive(Copy, Clone, Debug, Fail, Eq, PartialEq)]
-#[fail(display = "invalid file header")]
-pub struct InvalidFileHeader(());
+pub enum InvalidFileHeader {
+    #[fail(display = "not a pack file")]
+    Magic,
+    #[fail(display = "unsupported pack version {}", _0)]
+    Version(u32)
+}
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct FileHeaderParser(FileHeaderParserState);
 
 impl Default for FileHeaderParser {
     fn default() -> FileHeaderParser {
-        FileHeaderParser(FileHeaderParserState::Tag(0))
+        FileHeaderParser(FileHeaderParserState::Magic(0))
     }
 }
 
 #[derive(Debug, Eq, PartialEq)]
 enum FileHeaderParserState {
-    Tag(usize),
-    Count(gulp::Bytes<[u8; 4]>)
+    Magic(usize),
+    Version(gulp::Bytes<[u8; 4]>),
+    Count(u32, gulp::Bytes<[u8; 4]>)
 }
 
 impl Parse for FileHeaderParser {
@@ -33,36 +73,87 @@ impl Parse for FileHeaderParser {
     type Err = InvalidFileHeader;
     fn parse(self, buf: &[u8]) -> ParseResult<Self> {
         match self.0 {
-            FileHeaderParserState::Tag(n)   => FileHeaderParser::parse_tag(n, buf),
-            FileHeaderParserState::Count(p) => FileHeaderParser::parse_count(p, buf)
+            FileHeaderParserState::Magic(n)          => FileHeaderParser::parse_magic(n, buf),
+            FileHeaderParserState::Version(p)        => FileHeaderParser::parse_version(p, buf),
+            FileHeaderParserState::Count(version, p) => FileHeaderParser::parse_count(version, p, buf)
         }
     }
 }
 
 impl FileHeaderParser {
-    fn parse_tag(n: usize, buf: &[u8]) -> ParseResult<Self> {
-        const TAG: &'static [u8] = b"PACK\x00\x00\x00\x02";
-        let mut buf = buf.iter();
-        let mut tag = TAG[n..].iter();
-        while let Some((b, t)) = (&mut tag).zip(&mut buf).next() {
-            if b != t {
-                return gulp::Result::Err(InvalidFileHeader(()));
-            }
+    fn parse_magic(n: usize, buf: &[u8]) -> ParseResult<Self> {
+        const MAGIC: &[u8] = b"PACK";
+        let len = cmp::min(MAGIC.len() - n, buf.len());
+        if buf[..len] != MAGIC[n..n + len] {
+            return gulp::Result::Err(InvalidFileHeader::Magic);
         }
-        if tag.len() != 0 {
-            gulp::Result::Incomplete(FileHeaderParser(FileHeaderParserState::Tag(TAG.len() - tag.len() - 1)))
+        if n + len < MAGIC.len() {
+            gulp::Result::Incomplete(FileHeaderParser(FileHeaderParserState::Magic(n + len)))
         } else {
-            FileHeaderParser::parse_count(gulp::Bytes::default(), buf.as_slice())
+            FileHeaderParser::parse_version(gulp::Bytes::default(), &buf[len..])
         }
     }
-    fn parse_count(p: gulp::Bytes<[u8; 4]>, buf: &[u8]) -> ParseResult<Self> {
+    fn parse_version(p: gulp::Bytes<[u8; 4]>, buf: &[u8]) -> ParseResult<Self> {
         match p.parse(buf) {
-            gulp::Result::Incomplete(p) => gulp::Result::Incomplete(FileHeaderParser(FileHeaderParserState::Count(p))),
+            gulp::Result::Incomplete(p) => gulp::Result::Incomplete(FileHeaderParser(FileHeaderParserState::Version(p))),
+            gulp::Result::Err(e) => match e {},
+            gulp::Result::Ok(version, tail) => {
+                use byteorder::ByteOrder;
+                let version = byteorder::NetworkEndian::read_u32(&version);
+                if !FileHeader::VERSIONS.contains(&version) {
+                    return gulp::Result::Err(InvalidFileHeader::Version(version));
+                }
+                FileHeaderParser::parse_count(version, gulp::Bytes::default(), tail)
+            }
+        }
+    }
+    fn parse_count(version: u32, p: gulp::Bytes<[u8; 4]>, buf: &[u8]) -> ParseResult<Self> {
+        match p.parse(buf) {
+            gulp::Result::Incomplete(p) => gulp::Result::Incomplete(FileHeaderParser(FileHeaderParserState::Count(version, p))),
             gulp::Result::Err(e) => match e {},
             gulp::Result::Ok(count, tail) => {
                 use byteorder::ByteOrder;
                 let count = byteorder::NetworkEndian::read_u32(&count);
-                gulp::Result::Ok(FileHeader { count }, tail)
+                gulp::Result::Ok(FileHeader { version, count }, tail)
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Fail, Eq, PartialEq)]
+pub enum InvalidTrailer {
+    #[fail(display = "pack checksum mismatch")]
+    ChecksumMismatch,
+    #[fail(display = "trailing data after pack checksum")]
+    TrailingData,
+    #[fail(display = "SHA-1 collision attack detected in pack")]
+    Collision
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct TrailerParser {
+    checksum: git::ObjectId,
+    inner: git::ObjectIdParser
+}
+
+impl TrailerParser {
+    pub fn new(checksum: git::ObjectId) -> TrailerParser {
+        TrailerParser { checksum, inner: git::ObjectIdParser::new(checksum.hash()) }
+    }
+}
+
+impl Parse for TrailerParser {
+    type Output = git::ObjectId;
+    type Err = InvalidTrailer;
+    fn parse(self, buf: &[u8]) -> ParseResult<Self> {
+        let checksum = self.checksum;
+        match self.inner.parse(buf) {
+            gulp::Result::Incomplete(inner) => gulp::Result::Incomplete(TrailerParser { checksum, inner }),
+            gulp::Result::Err(e) => match e {},
+            gulp::Result::Ok(stored, tail) => if stored == checksum {
+                gulp::Result::Ok(stored, tail)
+            } else {
+                gulp::Result::Err(InvalidTrailer::ChecksumMismatch)
             }
         }
     }
@@ -83,12 +174,51 @@ impl From<DeltaHeader> for EntryHeader {
 }
 
 impl EntryHeader {
+    pub const MAX_LEN: usize = 42;
+
     pub fn kind(&self) -> EntryKind {
         match *self {
             EntryHeader::Object(ref h) => From::from(h.kind),
             EntryHeader::Delta(ref h)  => From::from(h.kind())
         }
     }
+    pub fn size(&self) -> u64 {
+        match *self {
+            EntryHeader::Object(ref h) => h.size,
+            EntryHeader::Delta(ref h)  => h.delta_len
+        }
+    }
+    pub fn encode<'a>(&self, buf: &'a mut [u8; EntryHeader::MAX_LEN]) -> &'a [u8] {
+        let size = self.size();
+        buf[0] = self.kind().code() << 4 | (size & 15) as u8;
+        let mut size = size >> 4;
+        let mut n = 1;
+        while size != 0 {
+            buf[n - 1] |= 0x80;
+            buf[n] = (size & 0x7F) as u8;
+            size >>= 7;
+            n += 1;
+        }
+        match *self {
+            EntryHeader::Object(_) => {}
+            EntryHeader::Delta(ref h) => match h.base {
+                DeltaBase::Offset(off) => n += DeltaOffsetParser::encode(off, &mut buf[n..]),
+                DeltaBase::Reference(id) => {
+                    let id = id.as_bytes();
+                    buf[n..n + id.len()].copy_from_slice(id);
+                    n += id.len();
+                }
+            }
+        }
+        &buf[..n]
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct EntrySpan {
+    pub offset: u64,
+    pub len: u64,
+    pub crc32: u32
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -97,6 +227,19 @@ pub enum EntryKind {
     Delta(DeltaKind)
 }
 
+impl EntryKind {
+    fn code(&self) -> u8 {
+        match *self {
+            EntryKind::Object(git::ObjectKind::Commit) => 1,
+            EntryKind::Object(git::ObjectKind::Tree)   => 2,
+            EntryKind::Object(git::ObjectKind::Blob)   => 3,
+            EntryKind::Object(git::ObjectKind::Tag)    => 4,
+            EntryKind::Delta(DeltaKind::Offset)        => 6,
+            EntryKind::Delta(DeltaKind::Reference)     => 7
+        }
+    }
+}
+
 impl From<git::ObjectKind> for EntryKind {
     fn from(k: git::ObjectKind) -> EntryKind { EntryKind::Object(k) }
 }
@@ -107,12 +250,18 @@ impl From<DeltaKind> for EntryKind {
 
 impl Default for EntryHeaderParser {
     fn default() -> EntryHeaderParser {
-        EntryHeaderParser(EntryHeaderParserState::Fresh)
+        EntryHeaderParser::new(git::HashKind::Sha1)
     }
 }
 
 #[derive(Debug, Eq, PartialEq)]
-pub struct EntryHeaderParser(EntryHeaderParserState);
+pub struct EntryHeaderParser(git::HashKind, EntryHeaderParserState);
+
+impl EntryHeaderParser {
+    pub fn new(hash: git::HashKind) -> EntryHeaderParser {
+        EntryHeaderParser(hash, EntryHeaderParserState::Fresh)
+    }
+}
 
 #[derive(Debug, Eq, PartialEq)]
 enum EntryHeaderParserState {
@@ -125,19 +274,20 @@ impl Parse for EntryHeaderParser {
     type Output = EntryHeader;
     type Err = InvalidEntryHeader;
     fn parse(self, buf: &[u8]) -> ParseResult<Self> {
-        match self.0 {
-            EntryHeaderParserState::Fresh         => EntryHeaderParser::parse_fresh(buf),
-            EntryHeaderParserState::Size(kind, p) => EntryHeaderParser::parse_size(kind, p, buf),
-            EntryHeaderParserState::Delta(p)      => EntryHeaderParser::parse_delta(p, buf)
+        let hash = self.0;
+        match self.1 {
+            EntryHeaderParserState::Fresh         => EntryHeaderParser::parse_fresh(hash, buf),
+            EntryHeaderParserState::Size(kind, p) => EntryHeaderParser::parse_size(hash, kind, p, buf),
+            EntryHeaderParserState::Delta(p)      => EntryHeaderParser::parse_delta(hash, p, buf)
         }
     }
 }
 
 impl EntryHeaderParser {
-    fn parse_fresh(buf: &[u8]) -> ParseResult<Self> {
+    fn parse_fresh(hash: git::HashKind, buf: &[u8]) -> ParseResult<Self> {
         let mut buf = buf.iter();
         let byte = match buf.next() {
-            None => return gulp::Result::Incomplete(EntryHeaderParser(EntryHeaderParserState::Fresh)),
+            None => return gulp::Result::Incomplete(EntryHeaderParser(hash, EntryHeaderParserState::Fresh)),
             Some(&b) => b
         };
         let kind: EntryKind = match (byte>>4) & 7 {
@@ -151,27 +301,27 @@ impl EntryHeaderParser {
         };
         let size = byte as u64 & 15;
         if byte&0x80 != 0 {
-            EntryHeaderParser::parse_size(kind, gulp::Leb128::new(4, size), buf.as_slice())
+            EntryHeaderParser::parse_size(hash, kind, gulp::Leb128::new(4, size), buf.as_slice())
         } else {
-            EntryHeaderParser::parse_tail(kind, size, buf.as_slice())
+            EntryHeaderParser::parse_tail(hash, kind, size, buf.as_slice())
         }
     }
-    fn parse_size(kind: EntryKind, p: gulp::Leb128, buf: &[u8]) -> ParseResult<Self> {
+    fn parse_size(hash: git::HashKind, kind: EntryKind, p: gulp::Leb128, buf: &[u8]) -> ParseResult<Self> {
         match p.parse(buf) {
-            gulp::Result::Incomplete(p) => gulp::Result::Incomplete(EntryHeaderParser(EntryHeaderParserState::Size(kind, p))),
+            gulp::Result::Incomplete(p) => gulp::Result::Incomplete(EntryHeaderParser(hash, EntryHeaderParserState::Size(kind, p))),
             gulp::Result::Err(gulp::Overflow) => gulp::Result::Err(InvalidEntryHeader(())),
-            gulp::Result::Ok(size, tail) => EntryHeaderParser::parse_tail(kind, size, tail)
+            gulp::Result::Ok(size, tail) => EntryHeaderParser::parse_tail(hash, kind, size, tail)
         }
     }
-    fn parse_tail(kind: EntryKind, size: u64, buf: &[u8]) -> ParseResult<Self> {
+    fn parse_tail(hash: git::HashKind, kind: EntryKind, size: u64, buf: &[u8]) -> ParseResult<Self> {
         match kind {
             EntryKind::Object(kind) => gulp::Result::Ok(From::from(git::ObjectHeader { kind, size }), buf),
-            EntryKind::Delta(kind)  => EntryHeaderParser::parse_delta(DeltaHeaderParser::new(size, kind), buf)
+            EntryKind::Delta(kind)  => EntryHeaderParser::parse_delta(hash, DeltaHeaderParser::new(size, kind, hash), buf)
         }
     }
-    fn parse_delta(p: DeltaHeaderParser, buf: &[u8]) -> ParseResult<Self> {
+    fn parse_delta(hash: git::HashKind, p: DeltaHeaderParser, buf: &[u8]) -> ParseResult<Self> {
         match p.parse(buf) {
-            gulp::Result::Incomplete(p) => gulp::Result::Incomplete(EntryHeaderParser(EntryHeaderParserState::Delta(p))),
+            gulp::Result::Incomplete(p) => gulp::Result::Incomplete(EntryHeaderParser(hash, EntryHeaderParserState::Delta(p))),
             gulp::Result::Err(InvalidDeltaHeader) => gulp::Result::Err(InvalidEntryHeader(())),
             gulp::Result::Ok(header, tail) => gulp::Result::Ok(From::from(header), tail)
         }
@@ -217,10 +367,10 @@ enum DeltaHeaderParser {
 }
 
 impl DeltaHeaderParser {
-    fn new(delta_len: u64, kind: DeltaKind) -> DeltaHeaderParser {
+    fn new(delta_len: u64, kind: DeltaKind, hash: git::HashKind) -> DeltaHeaderParser {
         match kind {
             DeltaKind::Offset    => DeltaHeaderParser::Offset(delta_len, DeltaOffsetParser::Fresh),
-            DeltaKind::Reference => DeltaHeaderParser::Reference(delta_len, git::ObjectIdParser::default())
+            DeltaKind::Reference => DeltaHeaderParser::Reference(delta_len, git::ObjectIdParser::new(hash))
         }
     }
 }
@@ -278,8 +428,7 @@ impl DeltaOffsetParser {
     fn parse_off(mut off: u64, buf: &[u8]) -> ParseResult<Self> {
         let mut buf = buf.iter();
         while let Some(&b) = buf.next() {
-            off += 1;
-            off = match off.safe_shl(7) {
+            off = match off.checked_add(1).and_then(|off| off.safe_shl(7)) {
                 None => return gulp::Result::Err(InvalidDeltaHeader),
                 Some(off) => off
             };
@@ -290,8 +439,83 @@ impl DeltaOffsetParser {
         }
         gulp::Result::Incomplete(DeltaOffsetParser::Offset(off))
     }
+    fn encode(mut off: u64, buf: &mut [u8]) -> usize {
+        let mut tmp = [0; 10];
+        let mut pos = tmp.len() - 1;
+        tmp[pos] = (off & 0x7F) as u8;
+        off >>= 7;
+        while off != 0 {
+            off -= 1;
+            pos -= 1;
+            tmp[pos] = 0x80 | (off & 0x7F) as u8;
+            off >>= 7;
+        }
+        let len = tmp.len() - pos;
+        buf[..len].copy_from_slice(&tmp[pos..]);
+        len
+    }
 }
 
 #[derive(Copy, Clone, Debug, Fail)]
 #[fail(display = "invalid delta header")]
 struct InvalidDeltaHeader;
+
+#[test]
+fn entry_header_round_trip() {
+    use rand::{Rng, SeedableRng};
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0x9E37_79B9_7F4A_7C15);
+    // sizes and offsets of every length
+    let mut random = move || rng.gen::<u64>() >> rng.gen_range(0..64);
+    let kinds = [git::ObjectKind::Commit, git::ObjectKind::Tree, git::ObjectKind::Blob, git::ObjectKind::Tag];
+    for i in 0..10_000 {
+        let size = random();
+        let header: EntryHeader = match i % 6 {
+            4 => From::from(DeltaHeader { delta_len: size, base: DeltaBase::Offset(random()) }),
+            5 => From::from(DeltaHeader { delta_len: size, base: DeltaBase::Reference(git::ObjectId::Sha1([i as u8; 20])) }),
+            k => From::from(git::ObjectHeader { kind: kinds[k % 4], size })
+        };
+        let mut buf = [0; EntryHeader::MAX_LEN];
+        let encoded = header.encode(&mut buf);
+        assert_eq!(EntryHeaderParser::default().parse(encoded), gulp::Result::Ok(header, &[][..]));
+    }
+    for i in 0..100 {
+        let header = EntryHeader::Delta(DeltaHeader { delta_len: u64::MAX, base: DeltaBase::Reference(git::ObjectId::Sha256([i; 32])) });
+        let mut buf = [0; EntryHeader::MAX_LEN];
+        let encoded = header.encode(&mut buf);
+        assert_eq!(encoded.len(), EntryHeader::MAX_LEN);
+        assert_eq!(EntryHeaderParser::new(git::HashKind::Sha256).parse(encoded), gulp::Result::Ok(header, &[][..]));
+    }
+    for &off in &[0, 0x7F, 0x80, 0x407F, 0x4080, u64::MAX] {
+        let header = EntryHeader::Delta(DeltaHeader { delta_len: u64::MAX, base: DeltaBase::Offset(off) });
+        let mut buf = [0; EntryHeader::MAX_LEN];
+        assert_eq!(EntryHeaderParser::default().parse(header.encode(&mut buf)), gulp::Result::Ok(header, &[][..]));
+    }
+}
+
+#[test]
+fn parse_file_header() {
+    for &version in FileHeader::VERSIONS {
+        let header = FileHeader { version, count: 0x0102_0304 };
+        assert_eq!(FileHeaderParser::default().parse(&header.encode()), gulp::Result::Ok(header, &[][..]));
+    }
+    assert_eq!(FileHeader::new(7).encode(), *b"PACK\x00\x00\x00\x02\x00\x00\x00\x07");
+    assert_eq!(FileHeaderParser::default().parse(b"PACK\x00\x00\x00\x04\x00\x00\x00\x00"), gulp::Result::Err(InvalidFileHeader::Version(4)));
+    assert_eq!(FileHeaderParser::default().parse(b"PACX"), gulp::Result::Err(InvalidFileHeader::Magic));
+    assert_eq!(FileHeaderParser::default().parse(b"\x00\x00\x00\x02"), gulp::Result::Err(InvalidFileHeader::Magic));
+
+    // a byte at a time, as from a slow reader
+    let header = FileHeader { version: 3, count: 9 };
+    let mut parser = FileHeaderParser::default();
+    for &b in &header.encode()[..11] {
+        parser = match parser.parse(&[b]) {
+            gulp::Result::Incomplete(p) => p,
+            r => panic!("{:?}", r)
+        };
+    }
+    assert_eq!(parser.parse(b"\x09tail"), gulp::Result::Ok(header, &b"tail"[..]));
+    match FileHeaderParser::default().parse(b"PAC") {
+        gulp::Result::Incomplete(p) => assert_eq!(p.parse(b"X"), gulp::Result::Err(InvalidFileHeader::Magic)),
+        r => panic!("{:?}", r)
+    }
+}
@@ -0,0 +1,224 @@
+use std::{cmp, fs, io};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use io_at::Fd;
+use crate::{IndexFile, Pack, PackObjectReader};
+
+// git ignores alternates nested deeper than this
+const MAX_ALTERNATE_DEPTH: usize = 5;
+
+// every object reachable from an objects directory: its packs, its loose objects, and its alternates
+pub struct ObjectDatabase {
+    objects: PathBuf,
+    hash: git::HashKind,
+    packs: Vec<Pack<Fd<fs::File>>>,
+    // packs that couldn't be opened, which like git we leave out rather than give up on the rest
+    skipped: Vec<(PathBuf, io::Error)>,
+    alternates: Vec<ObjectDatabase>
+}
+
+impl ObjectDatabase {
+    pub fn open(objects: &Path) -> io::Result<ObjectDatabase> {
+        ObjectDatabase::open_with_hash(git::HashKind::Sha1, objects)
+    }
+    pub fn open_with_hash(hash: git::HashKind, objects: &Path) -> io::Result<ObjectDatabase> {
+        ObjectDatabase::open_nested(hash, objects, 0, &mut vec![objects.canonicalize()?])
+    }
+    fn open_nested(hash: git::HashKind, objects: &Path, depth: usize, seen: &mut Vec<PathBuf>) -> io::Result<ObjectDatabase> {
+        let (mut packs, mut skipped) = (Vec::new(), Vec::new());
+        for entry in read_dir_if_exists(&objects.join("pack"))? {
+            let path = entry?.path();
+            if path.extension() != Some("idx".as_ref()) || !path.with_extension("pack").exists() {
+                continue;
+            }
+            match open_pack(hash, &path) {
+                Ok(pack) => packs.push(pack),
+                Err(e) => skipped.push((path, e))
+            }
+        }
+        // like git, look in the most recently written packs first
+        packs.sort_by_key(|&(modified, _)| cmp::Reverse(modified));
+        let packs = packs.into_iter().map(|(_, pack)| pack).collect();
+
+        let mut alternates = Vec::new();
+        if depth < MAX_ALTERNATE_DEPTH {
+            for path in read_alternates(objects)? {
+                let path = match path.canonicalize() {
+                    Ok(path) => path,
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e)
+                };
+                if seen.contains(&path) {
+                    continue;
+                }
+                seen.push(path.clone());
+                alternates.push(ObjectDatabase::open_nested(hash, &path, depth + 1, seen)?);
+            }
+        }
+
+        Ok(ObjectDatabase { objects: objects.to_owned(), hash, packs, skipped, alternates })
+    }
+    pub fn path(&self) -> &Path {
+        &self.objects
+    }
+    pub fn hash(&self) -> git::HashKind {
+        self.hash
+    }
+    // the .idx files of packs that were left out, and why
+    pub fn skipped(&self) -> &[(PathBuf, io::Error)] {
+        &self.skipped
+    }
+    pub fn contains(&self, id: &git::ObjectId) -> io::Result<bool> {
+        Ok(self.header(id)?.is_some())
+    }
+    pub fn header(&self, id: &git::ObjectId) -> io::Result<Option<git::ObjectHeader>> {
+        self.check_hash(id)?;
+        for pack in &self.packs {
+            if let Some(offset) = pack.find(id)? {
+                return pack.header(offset).map(Some);
+            }
+        }
+        if let Some(reader) = self.loose(id)? {
+            return Ok(Some(reader.header()));
+        }
+        for alternate in &self.alternates {
+            if let Some(header) = alternate.header(id)? {
+                return Ok(Some(header));
+            }
+        }
+        Ok(None)
+    }
+    pub fn read(&self, id: &git::ObjectId) -> io::Result<Option<ObjectReader<'_>>> {
+        self.check_hash(id)?;
+        for pack in &self.packs {
+            if let Some(offset) = pack.find(id)? {
+                return Ok(Some(ObjectReader(ObjectReaderInner::Packed(pack.reader(offset)?))));
+            }
+        }
+        if let Some(reader) = self.loose(id)? {
+            return Ok(Some(ObjectReader(ObjectReaderInner::Loose(Box::new(reader)))));
+        }
+        for alternate in &self.alternates {
+            if let Some(reader) = alternate.read(id)? {
+                return Ok(Some(reader));
+            }
+        }
+        Ok(None)
+    }
+    fn loose(&self, id: &git::ObjectId) -> io::Result<Option<git::LooseReader<io::BufReader<fs::File>>>> {
+        match git::open_loose(&self.objects, id) {
+            Ok(reader) => Ok(Some(reader)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+    fn check_hash(&self, id: &git::ObjectId) -> io::Result<()> {
+        if id.hash() != self.hash {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "object id uses a different hash than the object database"));
+        }
+        Ok(())
+    }
+}
+
+fn open_pack(hash: git::HashKind, path: &Path) -> io::Result<(SystemTime, Pack<Fd<fs::File>>)> {
+    let modified = fs::metadata(path)?.modified()?;
+    let index = IndexFile::open_with_hash(hash, Fd::new(fs::File::open(path)?))?;
+    let pack = Fd::new(fs::File::open(path.with_extension("pack"))?);
+    Ok((modified, Pack::with_index(pack, index)?))
+}
+
+fn read_dir_if_exists(path: &Path) -> io::Result<Vec<io::Result<fs::DirEntry>>> {
+    match fs::read_dir(path) {
+        Ok(entries) => Ok(entries.collect()),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e)
+    }
+}
+
+// one path per line, relative to the objects directory, skipping blank lines and comments
+fn read_alternates(objects: &Path) -> io::Result<Vec<PathBuf>> {
+    let alternates = match fs::read_to_string(objects.join("info").join("alternates")) {
+        Ok(alternates) => alternates,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e)
+    };
+    Ok(alternates.lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| objects.join(line))
+        .collect())
+}
+
+pub struct ObjectReader<'a>(ObjectReaderInner<'a>);
+
+enum ObjectReaderInner<'a> {
+    Loose(Box<git::LooseReader<io::BufReader<fs::File>>>),
+    Packed(PackObjectReader<'a, Fd<fs::File>>)
+}
+
+impl ObjectReader<'_> {
+    pub fn header(&self) -> git::ObjectHeader {
+        match self.0 {
+            ObjectReaderInner::Loose(ref reader) => reader.header(),
+            ObjectReaderInner::Packed(ref reader) => reader.header()
+        }
+    }
+}
+
+impl Read for ObjectReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0 {
+            ObjectReaderInner::Loose(ref mut reader) => reader.read(buf),
+            ObjectReaderInner::Packed(ref mut reader) => reader.read(buf)
+        }
+    }
+}
+
+#[test]
+fn object_database() {
+    use std::io::Write;
+    use crate::test_pack::{blob, Entry, TestPack};
+
+    let root = std::env::temp_dir().join(format!("git-odb-test-{}", std::process::id()));
+    let (objects, shared) = (root.join("objects"), root.join("shared"));
+    fs::create_dir_all(objects.join("pack")).unwrap();
+    fs::create_dir_all(objects.join("info")).unwrap();
+    fs::create_dir_all(&shared).unwrap();
+    writeln!(fs::File::create(objects.join("info").join("alternates")).unwrap(), "# shared objects\n../shared").unwrap();
+
+    let (base, ofs, rf) = (&b"hello, world"[..], &b"hello, world!"[..], &b"hello, world!?"[..]);
+    let pack = TestPack::new(&[Entry::Whole(base), Entry::Offset(0, ofs), Entry::Reference(ofs, rf)]);
+    let name = objects.join("pack").join(format!("pack-{}", pack.checksum));
+    fs::write(name.with_extension("pack"), &pack.data).unwrap();
+    fs::write(name.with_extension("idx"), pack.index()).unwrap();
+
+    let loose = |objects: &Path, data: &[u8]| {
+        let mut writer = git::LooseWriter::new(objects, git::ObjectHeader { kind: git::ObjectKind::Blob, size: data.len() as u64 }).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    };
+    loose(&objects, b"loose");
+    loose(&shared, b"shared");
+
+    // a broken pack is skipped without hiding the others
+    let broken = objects.join("pack").join("pack-broken");
+    fs::write(broken.with_extension("pack"), b"PACK").unwrap();
+    fs::write(broken.with_extension("idx"), b"not an index").unwrap();
+
+    let odb = ObjectDatabase::open(&objects).unwrap();
+    assert_eq!(odb.skipped().len(), 1);
+    assert_eq!(odb.skipped()[0].0, broken.with_extension("idx"));
+    for &data in &[base, ofs, rf, b"loose", b"shared"] {
+        let header = git::ObjectHeader { kind: git::ObjectKind::Blob, size: data.len() as u64 };
+        assert_eq!(odb.header(&blob(data)).unwrap(), Some(header));
+        let mut reader = odb.read(&blob(data)).unwrap().unwrap();
+        assert_eq!(reader.header(), header);
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body).unwrap();
+        assert_eq!(body, data);
+    }
+    assert!(!odb.contains(&blob(b"missing")).unwrap());
+    assert!(odb.read(&blob(b"missing")).unwrap().is_none());
+    assert_eq!(odb.header(&git::ObjectId::Sha256([0; 32])).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,296 @@
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use flate2::bufread::ZlibDecoder;
+use io_at::ReadAt;
+use crate::{CacheStats, DeltaBaseCache, IndexFile, FileHeaderParser, EntryHeader, EntryHeaderParser, DeltaHeader, DeltaBase, InvalidTrailer, invalid_data, parse_at, read_error, trailer_at};
+
+// random access to the objects in a pack, resolving deltas against their bases.
+// reference deltas can only be resolved when the pack comes with its index.
+pub struct Pack<R: ReadAt> {
+    reader: R,
+    hash: git::HashKind,
+    count: u32,
+    index: Option<IndexFile<R>>,
+    cache: Mutex<DeltaBaseCache>
+}
+
+impl<R: ReadAt> Pack<R> {
+    pub fn open(reader: R) -> io::Result<Pack<R>> {
+        Pack::open_with_hash(git::HashKind::Sha1, reader)
+    }
+    pub fn open_with_hash(hash: git::HashKind, reader: R) -> io::Result<Pack<R>> {
+        let (header, _) = parse_at(&reader, 0, FileHeaderParser::default())?;
+        Ok(Pack {
+            reader,
+            hash,
+            count: header.count,
+            index: None,
+            cache: Mutex::new(DeltaBaseCache::new(DeltaBaseCache::DEFAULT_BUDGET))
+        })
+    }
+    pub fn with_index(reader: R, index: IndexFile<R>) -> io::Result<Pack<R>> {
+        let mut pack = Pack::open_with_hash(index.header().hash, reader)?;
+        if pack.count != index.len() {
+            return Err(invalid_data("pack and index disagree on the number of objects"));
+        }
+        // like git, only trust an index that names this pack's checksum
+        if pack.checksum()? != index.pack_checksum()? {
+            return Err(invalid_data("index belongs to a different pack"));
+        }
+        pack.index = Some(index);
+        Ok(pack)
+    }
+    pub fn hash(&self) -> git::HashKind {
+        self.hash
+    }
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+    pub fn checksum(&self) -> io::Result<git::ObjectId> {
+        Ok(trailer_at::<_, InvalidTrailer>(&self.reader, self.hash, 0)?)
+    }
+    pub fn index(&self) -> Option<&IndexFile<R>> {
+        self.index.as_ref()
+    }
+    pub fn find(&self, id: &git::ObjectId) -> io::Result<Option<u64>> {
+        let index = match self.index {
+            Some(ref index) => index,
+            None => return Ok(None)
+        };
+        match index.find(*id)? {
+            Some(i) => Ok(Some(index.offset(i)?)),
+            None => Ok(None)
+        }
+    }
+    // the header of the entry at `offset`, and the offset of its compressed body
+    pub fn entry(&self, offset: u64) -> io::Result<(EntryHeader, u64)> {
+        let (header, len) = parse_at(&self.reader, offset, EntryHeaderParser::new(self.hash))?;
+        Ok((header, offset + len))
+    }
+    pub fn header(&self, offset: u64) -> io::Result<git::ObjectHeader> {
+        let mut deltas = Vec::new();
+        let (root, _) = self.chain(offset, &mut deltas)?;
+        let size = match deltas.first() {
+            None => root.size,
+            Some(&(body, _)) => {
+                let mut delta = io::BufReader::new(self.inflate(body));
+                gulp::from_reader(&mut delta, git_delta::HeaderParser::default)?.result_len
+            }
+        };
+        Ok(git::ObjectHeader { kind: root.kind, size })
+    }
+    pub fn read(&self, offset: u64) -> io::Result<(git::ObjectHeader, Vec<u8>)> {
+        let (kind, data) = self.resolve(offset)?;
+        let data = Arc::try_unwrap(data).unwrap_or_else(|data| (*data).clone());
+        Ok((git::ObjectHeader { kind, size: data.len() as u64 }, data))
+    }
+    pub fn set_cache_budget(&mut self, budget: usize) {
+        self.cache().set_budget(budget);
+    }
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache().stats()
+    }
+    // whole objects are inflated as they're read, deltified ones are resolved up front
+    pub fn reader(&self, offset: u64) -> io::Result<PackObjectReader<'_, R>> {
+        match self.entry(offset)? {
+            (EntryHeader::Object(header), body) => Ok(PackObjectReader(PackObjectReaderInner::Whole {
+                header,
+                inflate: self.inflate(body),
+                remaining: header.size
+            })),
+            (EntryHeader::Delta(_), _) => {
+                let (header, data) = self.read(offset)?;
+                Ok(PackObjectReader(PackObjectReaderInner::Resolved(header, io::Cursor::new(data))))
+            }
+        }
+    }
+    // like `chain`, but stops early at a cached base, and caches the bases it had to rebuild
+    fn resolve(&self, mut offset: u64) -> io::Result<(git::ObjectKind, Arc<Vec<u8>>)> {
+        let mut deltas = Vec::new();
+        let (kind, mut data) = loop {
+            if let Some(cached) = self.cache().get(offset) {
+                break cached;
+            }
+            let (header, body) = self.entry(offset)?;
+            match header {
+                EntryHeader::Object(header) => {
+                    let mut data = Vec::new();
+                    self.inflate(body).take(header.size).read_to_end(&mut data)?;
+                    if data.len() as u64 != header.size {
+                        return Err(invalid_data("entry is shorter than its header says"));
+                    }
+                    let data = Arc::new(data);
+                    if !deltas.is_empty() {
+                        self.cache().insert(offset, header.kind, data.clone());
+                    }
+                    break (header.kind, data);
+                }
+                EntryHeader::Delta(delta) => {
+                    if deltas.len() as u64 >= self.count as u64 {
+                        return Err(invalid_data("delta chain loops"));
+                    }
+                    deltas.push((offset, body, delta));
+                    offset = self.base(offset, delta)?;
+                }
+            }
+        };
+
+        let mut delta = Vec::new();
+        for (i, &(offset, body, header)) in deltas.iter().enumerate().rev() {
+            delta.clear();
+            self.inflate(body).take(header.delta_len).read_to_end(&mut delta)?;
+            if delta.len() as u64 != header.delta_len {
+                return Err(invalid_data("entry is shorter than its header says"));
+            }
+            let mut output = Vec::new();
+            git_delta::ReaderAt::new_strict(&data[..], data.len() as u64, &delta[..])?.read_to_end(&mut output)?;
+            data = Arc::new(output);
+            if i != 0 {
+                self.cache().insert(offset, kind, data.clone());
+            }
+        }
+        Ok((kind, data))
+    }
+    fn cache(&self) -> MutexGuard<'_, DeltaBaseCache> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    fn inflate(&self, offset: u64) -> ZlibDecoder<io::BufReader<Cursor<'_, R>>> {
+        inflate_at(&self.reader, offset)
+    }
+    fn base(&self, offset: u64, delta: DeltaHeader) -> io::Result<u64> {
+        match delta.base {
+            DeltaBase::Offset(off) => match offset.checked_sub(off) {
+                Some(base) if off != 0 => Ok(base),
+                _ => Err(invalid_data("delta base offset precedes start of pack"))
+            },
+            DeltaBase::Reference(_) if self.index.is_none() => Err(invalid_data("can't resolve a reference delta without an index")),
+            DeltaBase::Reference(id) => self.find(&id)?.ok_or_else(|| invalid_data("delta base isn't in the pack"))
+        }
+    }
+    // follows the delta chain from `offset` until it reaches a whole object,
+    // collecting each delta's body offset and header along the way
+    fn chain(&self, mut offset: u64, deltas: &mut Vec<(u64, DeltaHeader)>) -> io::Result<(git::ObjectHeader, u64)> {
+        loop {
+            let (header, body) = self.entry(offset)?;
+            match header {
+                EntryHeader::Object(header) => return Ok((header, body)),
+                EntryHeader::Delta(delta) => {
+                    // reference deltas can form cycles, which a chain longer than the pack betrays
+                    if deltas.len() as u64 >= self.count as u64 {
+                        return Err(invalid_data("delta chain loops"));
+                    }
+                    deltas.push((body, delta));
+                    offset = self.base(offset, delta)?;
+                }
+            }
+        }
+    }
+}
+
+pub struct PackObjectReader<'a, R: ReadAt>(PackObjectReaderInner<'a, R>);
+
+enum PackObjectReaderInner<'a, R: ReadAt> {
+    Whole {
+        header: git::ObjectHeader,
+        inflate: ZlibDecoder<io::BufReader<Cursor<'a, R>>>,
+        remaining: u64
+    },
+    Resolved(git::ObjectHeader, io::Cursor<Vec<u8>>)
+}
+
+impl<R: ReadAt> PackObjectReader<'_, R> {
+    pub fn header(&self) -> git::ObjectHeader {
+        match self.0 {
+            PackObjectReaderInner::Whole { header, .. } => header,
+            PackObjectReaderInner::Resolved(header, _) => header
+        }
+    }
+}
+
+impl<R: ReadAt> Read for PackObjectReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0 {
+            PackObjectReaderInner::Whole { ref mut inflate, ref mut remaining, .. } => {
+                if *remaining == 0 || buf.is_empty() {
+                    return Ok(0);
+                }
+                let len = buf.len().min((*remaining).min(usize::MAX as u64) as usize);
+                let n = inflate.read(&mut buf[..len])?;
+                if n == 0 {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "entry is shorter than its header says"));
+                }
+                *remaining -= n as u64;
+                Ok(n)
+            }
+            PackObjectReaderInner::Resolved(_, ref mut reader) => reader.read(buf)
+        }
+    }
+}
+
+pub(crate) fn inflate_at<R: ReadAt + ?Sized>(reader: &R, offset: u64) -> ZlibDecoder<io::BufReader<Cursor<'_, R>>> {
+    ZlibDecoder::new(io::BufReader::new(Cursor { reader, offset }))
+}
+
+// reads sequentially from a ReadAt, starting at `offset`
+pub(crate) struct Cursor<'a, R: ReadAt + ?Sized> {
+    pub(crate) reader: &'a R,
+    pub(crate) offset: u64
+}
+
+impl<R: ReadAt + ?Sized> Read for Cursor<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read_at(self.offset, buf).map_err(read_error)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+#[test]
+fn pack_random_access() {
+    use crate::test_pack::{blob, Entry, TestPack};
+
+    let objects: [&[u8]; 4] = [b"hello, world", b"hello, world!", b"hello, world!?", b"goodbye, world!?"];
+    let test_pack = TestPack::new(&[
+        Entry::Whole(objects[0]),
+        Entry::Offset(0, objects[1]),
+        Entry::Reference(objects[1], objects[2]),
+        Entry::Offset(2, objects[3])
+    ]);
+    let (index, spans) = (test_pack.index(), &test_pack.entries);
+
+    let pack = Pack::with_index(&test_pack.data[..], IndexFile::open(&index[..]).unwrap()).unwrap();
+    assert_eq!(pack.count(), 4);
+    for ((data, span), id) in objects.iter().zip(spans).zip(test_pack.ids()) {
+        let header = git::ObjectHeader { kind: git::ObjectKind::Blob, size: data.len() as u64 };
+        assert_eq!(pack.find(&id).unwrap(), Some(span.offset));
+        assert_eq!(pack.header(span.offset).unwrap(), header);
+        assert_eq!(pack.read(span.offset).unwrap(), (header, data.to_vec()));
+        let mut reader = pack.reader(span.offset).unwrap();
+        assert_eq!(reader.read(&mut []).unwrap(), 0);
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body).unwrap();
+        assert_eq!((reader.header(), body), (header, data.to_vec()));
+    }
+    assert_eq!(pack.find(&blob(b"missing")).unwrap(), None);
+
+    // rereading a deltified object starts from the nearest cached base
+    let pack = Pack::with_index(pack.reader, IndexFile::open(&index[..]).unwrap()).unwrap();
+    assert_eq!(pack.read(spans[3].offset).unwrap().1, objects[3]);
+    assert_eq!(pack.cache_stats(), CacheStats { hits: 0, misses: 4, evictions: 0 });
+    assert_eq!(pack.read(spans[3].offset).unwrap().1, objects[3]);
+    assert_eq!(pack.cache_stats(), CacheStats { hits: 1, misses: 5, evictions: 0 });
+
+    // an index for another pack with as many objects is refused
+    let other = TestPack::new(&[Entry::Whole(b"a"), Entry::Whole(b"b"), Entry::Whole(b"c"), Entry::Whole(b"d")]);
+    let other_index = other.index();
+    assert_eq!(pack.checksum().unwrap(), test_pack.checksum);
+    let mismatched = Pack::with_index(&other.data[..], IndexFile::open(&index[..]).unwrap());
+    assert_eq!(mismatched.err().unwrap().kind(), io::ErrorKind::InvalidData);
+    let mismatched = Pack::with_index(&test_pack.data[..], IndexFile::open(&other_index[..]).unwrap());
+    assert_eq!(mismatched.err().unwrap().kind(), io::ErrorKind::InvalidData);
+
+    // without the index, only offset deltas can be followed
+    let pack = Pack::open(pack.reader).unwrap();
+    assert_eq!(pack.read(spans[1].offset).unwrap().1, objects[1]);
+    assert_eq!(pack.read(spans[3].offset).unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,114 @@
+use failure::Fail;
+use gulp::Parse;
+use io_at::ReadAt;
+
+#[derive(Debug, Fail)]
+pub enum ReadAtError<E: Fail, P: Fail> {
+    #[fail(display = "parse error: {}", _0)]
+    Parse(#[fail(cause)] P),
+    #[fail(display = "read error: {}", _0)]
+    Read(#[fail(cause)] E),
+    #[fail(display = "parse error: unexpected EOF")]
+    UnexpectedEof
+}
+
+#[cfg(feature = "std")]
+impl<E: Fail, P: Fail> From<ReadAtError<E, P>> for std::io::Error {
+    fn from(e: ReadAtError<E, P>) -> std::io::Error {
+        use std::io;
+        match e {
+            ReadAtError::Parse(e) => io::Error::new(io::ErrorKind::InvalidData, e.compat()),
+            ReadAtError::Read(e) => read_error(e),
+            ReadAtError::UnexpectedEof => io::Error::new(io::ErrorKind::UnexpectedEof, e.compat())
+        }
+    }
+}
+
+// the reader's own error untouched when it's already an io::Error, so its kind survives
+#[cfg(feature = "std")]
+pub(crate) fn read_error<E: Fail>(e: E) -> std::io::Error {
+    use std::any::Any;
+    let mut e = Some(e);
+    match (&mut e as &mut dyn Any).downcast_mut::<Option<std::io::Error>>() {
+        Some(e) => e.take().unwrap(),
+        None => std::io::Error::other(e.unwrap().compat())
+    }
+}
+
+pub type ReadAtResult<T, E, P> = Result<T, ReadAtError<E, P>>;
+
+pub fn parse_at<R: ReadAt + ?Sized, P: Parse>(reader: &R, offset: u64, mut parser: P) -> ReadAtResult<(P::Output, u64), R::Err, P::Err> {
+    let mut buf = [0; 256];
+    let mut pos = offset;
+    loop {
+        let n = reader.read_at(pos, &mut buf).map_err(ReadAtError::Read)?;
+        if n == 0 {
+            return Err(ReadAtError::UnexpectedEof);
+        }
+        match parser.parse(&buf[..n]) {
+            gulp::Result::Incomplete(p) => {
+                parser = p;
+                pos += n as u64;
+            }
+            gulp::Result::Err(e) => return Err(ReadAtError::Parse(e)),
+            gulp::Result::Ok(v, tail) => return Ok((v, pos + (n - tail.len()) as u64 - offset))
+        }
+    }
+}
+
+pub fn read_exact_at<R: ReadAt + ?Sized, P: Fail>(reader: &R, mut offset: u64, mut buf: &mut [u8]) -> ReadAtResult<(), R::Err, P> {
+    while !buf.is_empty() {
+        match reader.read_at(offset, buf).map_err(ReadAtError::Read)? {
+            0 => return Err(ReadAtError::UnexpectedEof),
+            n => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+// ReadAt has no notion of length, so look for where reads stop returning data
+pub fn len_at<R: ReadAt + ?Sized>(reader: &R) -> Result<u64, R::Err> {
+    let has = |off: u64| reader.read_at(off, &mut [0]).map(|n| n != 0);
+    let (mut lo, mut hi) = (0, 1);
+    while hi != u64::MAX && has(hi - 1)? {
+        lo = hi;
+        hi = hi.saturating_mul(2);
+    }
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if has(mid)? {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    Ok(lo)
+}
+
+// the checksum a file ends with, `skip` checksums from the end
+pub fn trailer_at<R: ReadAt + ?Sized, P: Fail>(reader: &R, hash: git::HashKind, skip: u64) -> ReadAtResult<git::ObjectId, R::Err, P> {
+    let len = hash.digest_len() as u64;
+    let offset = len_at(reader).map_err(ReadAtError::Read)?.checked_sub((skip + 1) * len).ok_or(ReadAtError::UnexpectedEof)?;
+    let mut buf = [0; 32];
+    read_exact_at(reader, offset, &mut buf[..len as usize])?;
+    Ok(git::ObjectId::from_bytes(hash, &buf[..len as usize]).unwrap())
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn io_errors() {
+    use std::io;
+    use crate::InvalidFileHeader;
+
+    let e: io::Error = ReadAtError::<io::Error, InvalidFileHeader>::Read(io::Error::new(io::ErrorKind::PermissionDenied, "no")).into();
+    assert_eq!((e.kind(), e.to_string()), (io::ErrorKind::PermissionDenied, "no".to_owned()));
+    let e: io::Error = ReadAtError::<io::Error, _>::Parse(InvalidFileHeader::Magic).into();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+    for len in 0..300 {
+        assert_eq!(len_at(&vec![0; len][..]).unwrap(), len as u64);
+    }
+}
@@ -0,0 +1,58 @@
+use crate::{Index, IndexEntry, PackWriter};
+
+pub(crate) fn blob(data: &[u8]) -> git::ObjectId {
+    let mut hasher = git::ObjectHasher::new(git::ObjectHeader { kind: git::ObjectKind::Blob, size: data.len() as u64 });
+    hasher.update(data);
+    hasher.digest().unwrap()
+}
+
+// a blob as it goes into a test pack
+#[derive(Copy, Clone)]
+pub(crate) enum Entry<'a> {
+    Whole(&'a [u8]),
+    // a delta against the blob written as the given entry, found by its offset
+    Offset(usize, &'a [u8]),
+    // a delta against the first blob, found by its id, which needn't be in the pack
+    Reference(&'a [u8], &'a [u8])
+}
+
+impl Entry<'_> {
+    pub(crate) fn data(&self) -> &[u8] {
+        match *self {
+            Entry::Whole(data) | Entry::Offset(_, data) | Entry::Reference(_, data) => data
+        }
+    }
+}
+
+pub(crate) struct TestPack {
+    pub(crate) data: Vec<u8>,
+    pub(crate) checksum: git::ObjectId,
+    // in the order the entries were written
+    pub(crate) entries: Vec<IndexEntry>
+}
+
+impl TestPack {
+    pub(crate) fn new(objects: &[Entry]) -> TestPack {
+        let mut writer = PackWriter::new(Vec::new(), objects.len() as u32).unwrap();
+        let mut entries: Vec<IndexEntry> = Vec::new();
+        for object in objects {
+            let span = match *object {
+                Entry::Whole(data) => writer.write_object(git::ObjectKind::Blob, data),
+                Entry::Offset(base, data) => writer.write_ofs_delta(entries[base].offset, &git_delta::encode(objects[base].data(), data)),
+                Entry::Reference(base, data) => writer.write_ref_delta(blob(base), &git_delta::encode(base, data))
+            }.unwrap();
+            entries.push(IndexEntry { id: blob(object.data()), offset: span.offset, crc32: span.crc32 });
+        }
+        let (data, checksum) = writer.finish().unwrap();
+        TestPack { data, checksum, entries }
+    }
+    pub(crate) fn ids(&self) -> Vec<git::ObjectId> {
+        self.entries.iter().map(|e| e.id).collect()
+    }
+    // the .idx, which needs every object in the pack
+    pub(crate) fn index(&self) -> Vec<u8> {
+        let mut index = Vec::new();
+        Index::from_entries(self.entries.clone(), self.checksum).unwrap().write_to(&mut index).unwrap();
+        index
+    }
+}
@@ -7,7 +7,7 @@ pub use os::Fd;
 #[cfg(feature = "std")]
 mod os;
 
-use core::cmp::max;
+use core::cmp::min;
 use void::Void;
 
 pub trait ReadAt {
@@ -15,7 +15,7 @@ pub trait ReadAt {
     fn read_at(&self, off: u64, buf: &mut [u8]) -> Result<usize, Self::Err>;
 }
 
-impl<R: ReadAt> ReadAt for &'_ R {
+impl<R: ReadAt + ?Sized> ReadAt for &'_ R {
     type Err = R::Err;
     fn read_at(&self, off: u64, buf: &mut [u8]) -> Result<usize, Self::Err> {
         R::read_at(self, off, buf)
@@ -27,7 +27,7 @@ pub trait WriteAt {
     fn write_at(&self, off: u64, buf: &[u8]) -> Result<usize, Self::Err>;
 }
 
-impl<W: WriteAt> WriteAt for &'_ W {
+impl<W: WriteAt + ?Sized> WriteAt for &'_ W {
     type Err = W::Err;
     fn write_at(&self, off: u64, buf: &[u8]) -> Result<usize, Self::Err> {
         W::write_at(self, off, buf)
@@ -42,7 +42,7 @@ impl ReadAt for [u8] {
         } else {
             &[]
         };
-        let len = max(r.len(), buf.len());
+        let len = min(r.len(), buf.len());
         buf[..len].copy_from_slice(&r[..len]);
         Ok(len)
     }
@@ -0,0 +1,30 @@
+#![no_main]
+extern crate libfuzzer_sys;
+
+// the first 320 bytes of the SHAttered PDFs, through the end of the two colliding blocks
+const SHATTERED: [&[u8]; 2] = [include_bytes!("../../test/shattered-1.bin"), include_bytes!("../../test/shattered-2.bin")];
+
+// the first byte picks the settings, and whether the rest follows one of the SHAttered prefixes,
+// so the collision path gets exercised along with plain hashing
+#[export_name = "rust_fuzzer_test_input"]
+pub extern "C" fn go(data: &[u8]) {
+    let (&flags, data) = match data.split_first() {
+        Some(split) => split,
+        None => return
+    };
+    let (mut c, mut rust) = (sha1dc::Hasher::new(), sha1dc::RustHasher::new());
+    c.set_safe_hash(flags & 1 != 0);
+    rust.set_safe_hash(flags & 1 != 0);
+    c.set_ubc_check(flags & 2 != 0);
+    rust.set_ubc_check(flags & 2 != 0);
+    if let Some(prefix) = SHATTERED.get((flags >> 2 & 3) as usize) {
+        c.update(prefix);
+        rust.update(prefix);
+    }
+    // and the data in pieces, to cover the buffering
+    for chunk in data.chunks((flags >> 4) as usize * 5 + 1) {
+        c.update(chunk);
+        rust.update(chunk);
+    }
+    assert_eq!(c.digest(), rust.digest());
+}
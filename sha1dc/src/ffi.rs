@@ -0,0 +1,88 @@
+#![allow(non_camel_case_types)]
+
+use core::mem;
+use crate::Collision;
+
+pub struct Hasher {
+    pub(crate) lower: SHA1_CTX
+}
+
+impl Hasher {
+    pub fn new() -> Hasher {
+        unsafe {
+            // all zeroes is a valid SHA1_CTX, and SHA1DCInit leaves the scratch space alone
+            let mut lower = mem::zeroed();
+            SHA1DCInit(&mut lower);
+            Hasher { lower }
+        }
+    }
+    pub fn set_safe_hash(&mut self, safe_hash: bool) {
+        unsafe {
+            SHA1DCSetSafeHash(&mut self.lower, safe_hash as c_int)
+        }
+    }
+    pub fn set_ubc_check(&mut self, ubc_check: bool) {
+        unsafe {
+            SHA1DCSetUseUBC(&mut self.lower, ubc_check as c_int)
+        }
+    }
+    pub fn update(&mut self, buffer: &[u8]) {
+        unsafe {
+            SHA1DCUpdate(&mut self.lower, buffer.as_ptr(), buffer.len())
+        }
+    }
+    pub fn digest(mut self) -> Result<[u8; 20], Collision> {
+        let mut digest = [0; 20];
+        match unsafe { SHA1DCFinal(digest.as_mut_ptr(), &mut self.lower) } {
+            0 => Ok(digest),
+            _ => Err(Collision { digest })
+        }
+    }
+}
+
+type c_int = i32;
+type collision_block_callback = extern "C" fn(byte_offset: u64, ihvin1: *const u32, ihvin2: *const u32, m1: *const u32, m2: *const u32);
+
+#[repr(C)]
+pub(crate) struct SHA1_CTX {
+    total: u64,
+    ihv: [u32; 5],
+    buffer: [u8; 64],
+    found_collision: c_int,
+    safe_hash: c_int,
+    detect_coll: c_int,
+    ubc_check: c_int,
+    reduced_round_coll: c_int,
+    callback: Option<collision_block_callback>,
+
+    ihv1: [u32; 5],
+    pub(crate) ihv2: [u32; 5],
+    m1: [u32; 80],
+    m2: [u32; 80],
+    states: [[u32; 5]; 80],
+}
+
+#[cfg(test)]
+#[repr(C)]
+pub(crate) struct dv_info_t {
+    dv_type: c_int,
+    dv_k: c_int,
+    dv_b: c_int,
+    pub(crate) testt: c_int,
+    maski: c_int,
+    maskb: c_int,
+    pub(crate) dm: [u32; 80]
+}
+
+extern "C" {
+    fn SHA1DCInit(ctx: *mut SHA1_CTX);
+    fn SHA1DCSetSafeHash(ctx: *mut SHA1_CTX, safe_hash: c_int);
+    fn SHA1DCSetUseUBC(ctx: *mut SHA1_CTX, ubc_check: c_int);
+    fn SHA1DCUpdate(ctx: *mut SHA1_CTX, buf: *const u8, len: usize);
+    fn SHA1DCFinal(digest: *mut u8, ctx: *mut SHA1_CTX) -> c_int;
+    #[cfg(test)]
+    pub(crate) fn ubc_check(w: *const u32, dvmask: *mut u32);
+    // terminated by an all-zero entry
+    #[cfg(test)]
+    pub(crate) static sha1_dvs: [dv_info_t; 33];
+}
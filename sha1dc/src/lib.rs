@@ -1,36 +1,49 @@
 #![no_std]
 
-use core::{mem, fmt};
+use core::fmt;
 
-pub struct Hasher {
-    lower: ffi::SHA1_CTX
+#[cfg(not(feature = "rust"))] pub use ffi::Hasher;
+#[cfg(feature = "rust")] pub use sha1::Hasher;
+
+#[cfg(not(feature = "rust"))] mod ffi;
+#[cfg(any(feature = "rust", feature = "fuzz", test))] mod sha1;
+#[cfg(any(feature = "rust", feature = "fuzz", test))] mod ubc_check;
+
+// the Rust port next to the C one, to fuzz them against each other
+#[cfg(all(feature = "fuzz", not(feature = "rust")))]
+#[doc(hidden)]
+pub use sha1::Hasher as RustHasher;
+
+// the input contains a block from a known SHA-1 collision attack
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Collision {
+    // with safe hashing on (the default), this is the hardened digest rather than plain SHA-1
+    pub digest: [u8; 20]
 }
 
-impl Hasher {
-    pub fn new() -> Hasher {
-        unsafe {
-            let mut lower = mem::uninitialized();
-            ffi::SHA1DCInit(&mut lower);
-            Hasher { lower }
-        }
+impl fmt::Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SHA-1 collision attack detected")
     }
-    pub fn update(&mut self, buffer: &[u8]) {
-        unsafe {
-            ffi::SHA1DCUpdate(&mut self.lower, buffer.as_ptr(), buffer.len())
-        }
+}
+
+impl Default for Hasher {
+    fn default() -> Hasher {
+        Hasher::new()
     }
-    pub fn digest(mut self) -> [u8; 20] {
-        unsafe {
-            let mut buffer: [u8; 20] = mem::uninitialized();
-            ffi::SHA1DCFinal(buffer.as_mut_ptr(), &mut self.lower);
-            buffer
-        }
+}
+
+#[cfg(all(feature = "fuzz", not(feature = "rust")))]
+impl Default for RustHasher {
+    fn default() -> RustHasher {
+        RustHasher::new()
     }
 }
 
 impl fmt::Write for Hasher {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        Ok(self.update(s.as_bytes()))
+        self.update(s.as_bytes());
+        Ok(())
     }
 }
 
@@ -42,36 +55,81 @@ fn hello_world() {
     write!(h, "hello, world").unwrap();
     let d = [0xb7, 0xe2, 0x3e, 0xc2, 0x9a, 0xf2, 0x2b, 0x0b, 0x4e, 0x41,
              0xda, 0x31, 0xe8, 0x68, 0xd5, 0x72, 0x26, 0x12, 0x1c, 0x84];
-    assert_eq!(d, h.digest());
+    assert_eq!(Ok(d), h.digest());
+
+    let mut h = Hasher::new();
+    h.set_safe_hash(false);
+    h.set_ubc_check(false);
+    write!(h, "hello, world").unwrap();
+    assert_eq!(Ok(d), h.digest());
 }
 
-#[allow(non_camel_case_types)]
-mod ffi {
-    type c_int = i32;
-    type collision_block_callback = extern "C" fn(byte_offset: u64, ihvin1: *const u32, ihvin2: *const u32, m1: *const u32, m2: *const u32);
-
-    #[repr(C)]
-    pub struct SHA1_CTX {
-        total: u64,
-        ihv: [u32; 5],
-        buffer: [u8; 64],
-        found_collision: c_int,
-        safe_hash: c_int,
-        detect_coll: c_int,
-        ubc_check: c_int,
-        reduced_round_coll: c_int,
-        callback: collision_block_callback,
-
-        ihv1: [u32; 5],
-        ihv2: [u32; 5],
-        m1: [u32; 80],
-        m2: [u32; 80],
-        states: [[u32; 5]; 80],
+// small enough for Miri, which can only run the Rust port
+#[test]
+fn shattered() {
+    // plain SHA-1 of either prefix
+    let sha1 = [0xf9, 0x2d, 0x74, 0xe3, 0x87, 0x45, 0x87, 0xaa, 0xf4, 0x43,
+                0xd1, 0xdb, 0x96, 0x1d, 0x4e, 0x26, 0xdd, 0xe1, 0x3e, 0x9c];
+    for prefix in &SHATTERED {
+        let mut h = Hasher::new();
+        h.update(prefix);
+        assert_ne!(h.digest().unwrap_err().digest, sha1);
+
+        let mut h = Hasher::new();
+        h.set_safe_hash(false);
+        h.update(prefix);
+        assert_eq!(h.digest(), Err(Collision { digest: sha1 }));
+    }
+}
+
+// the first 320 bytes of the SHAttered PDFs, through the end of the two colliding blocks
+#[cfg(test)]
+const SHATTERED: [&[u8]; 2] = [include_bytes!("../test/shattered-1.bin"), include_bytes!("../test/shattered-2.bin")];
+
+#[cfg(not(feature = "rust"))]
+#[test]
+fn matches_c() {
+    use rand::{Rng, SeedableRng};
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0x9E37_79B9_7F4A_7C15);
+
+    for (dv, c) in ubc_check::SHA1_DVS.iter().zip(unsafe { &ffi::sha1_dvs }) {
+        assert_eq!((dv.testt, &dv.dm[..]), (c.testt as usize, &c.dm[..]));
+    }
+    for _ in 0..100_000 {
+        let mut w = [0; 80];
+        rng.fill(&mut w[..]);
+        let mut mask = 0;
+        unsafe { ffi::ubc_check(w.as_ptr(), &mut mask) };
+        assert_eq!(ubc_check::ubc_check(&w), mask);
+    }
+
+    let mut data = [0; 4096];
+    rng.fill(&mut data[..]);
+    for i in 0..64 {
+        let (mut c, mut rust) = (ffi::Hasher::new(), sha1::Hasher::new());
+        // without the unavoidable bit conditions, every disturbance vector gets recompressed
+        c.set_ubc_check(i % 2 == 0);
+        rust.set_ubc_check(i % 2 == 0);
+        c.set_safe_hash(i % 4 < 2);
+        rust.set_safe_hash(i % 4 < 2);
+        let mut rest = &data[..rng.gen_range(0..data.len())];
+        while !rest.is_empty() {
+            let (chunk, tail) = rest.split_at(rng.gen_range(0..=rest.len()));
+            c.update(chunk);
+            rust.update(chunk);
+            assert_eq!(c.lower.ihv2, rust.ihv2);
+            rest = tail;
+        }
+        assert_eq!(c.digest(), rust.digest());
     }
 
-    extern "C" {
-        pub fn SHA1DCInit(ctx: *mut SHA1_CTX);
-        pub fn SHA1DCUpdate(ctx: *mut SHA1_CTX, buf: *const u8, len: usize);
-        pub fn SHA1DCFinal(digest: *mut u8, ctx: *mut SHA1_CTX);
+    for (i, prefix) in SHATTERED.iter().enumerate() {
+        let (mut c, mut rust) = (ffi::Hasher::new(), sha1::Hasher::new());
+        c.set_safe_hash(i % 2 == 0);
+        rust.set_safe_hash(i % 2 == 0);
+        c.update(prefix);
+        rust.update(prefix);
+        assert_eq!(c.digest(), rust.digest());
     }
 }
@@ -0,0 +1,154 @@
+use crate::Collision;
+use crate::ubc_check::{ubc_check, SHA1_DVS};
+
+// a port of the C implementation, trading its unrolled steps for loops
+pub struct Hasher {
+    total: u64,
+    ihv: [u32; 5],
+    buffer: [u8; 64],
+    found_collision: bool,
+    safe_hash: bool,
+    ubc_check: bool,
+    // where the last disturbance vector checked recompressed back to, as in SHA1_CTX
+    pub(crate) ihv2: [u32; 5]
+}
+
+impl Hasher {
+    pub fn new() -> Hasher {
+        Hasher {
+            total: 0,
+            ihv: [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0],
+            buffer: [0; 64],
+            found_collision: false,
+            safe_hash: true,
+            ubc_check: true,
+            ihv2: [0; 5]
+        }
+    }
+    pub fn set_safe_hash(&mut self, safe_hash: bool) {
+        self.safe_hash = safe_hash;
+    }
+    pub fn set_ubc_check(&mut self, ubc_check: bool) {
+        self.ubc_check = ubc_check;
+    }
+    pub fn update(&mut self, mut buffer: &[u8]) {
+        let left = (self.total & 63) as usize;
+        self.total += buffer.len() as u64;
+        if left != 0 {
+            let n = usize::min(64 - left, buffer.len());
+            self.buffer[left..left + n].copy_from_slice(&buffer[..n]);
+            if left + n < 64 {
+                return;
+            }
+            let block = self.buffer;
+            self.process(&block);
+            buffer = &buffer[n..];
+        }
+        let mut blocks = buffer.chunks_exact(64);
+        for block in &mut blocks {
+            self.process(block);
+        }
+        let rest = blocks.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+    }
+    pub fn digest(mut self) -> Result<[u8; 20], Collision> {
+        let bits = self.total << 3;
+        let last = (self.total & 63) as usize;
+        let mut padding = [0; 64];
+        padding[0] = 0x80;
+        self.update(&padding[..if last < 56 { 56 - last } else { 120 - last }]);
+        self.buffer[56..].copy_from_slice(&bits.to_be_bytes());
+        let block = self.buffer;
+        self.process(&block);
+
+        let mut digest = [0; 20];
+        for (out, word) in digest.chunks_exact_mut(4).zip(&self.ihv) {
+            out.copy_from_slice(&word.to_be_bytes());
+        }
+        if self.found_collision {
+            Err(Collision { digest })
+        } else {
+            Ok(digest)
+        }
+    }
+
+    fn process(&mut self, block: &[u8]) {
+        let mut w = [0; 80];
+        for (w, b) in w.iter_mut().zip(block.chunks_exact(4)) {
+            *w = u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+        }
+        for t in 16..80 {
+            w[t] = (w[t - 3] ^ w[t - 8] ^ w[t - 14] ^ w[t - 16]).rotate_left(1);
+        }
+
+        let mut states = [[0; 5]; 80];
+        let mut state = self.ihv;
+        for t in 0..80 {
+            states[t] = state;
+            state = step(t, state, w[t]);
+        }
+        self.ihv = add(self.ihv, state);
+
+        let mask = if self.ubc_check { ubc_check(&w) } else { !0 };
+        for (i, dv) in SHA1_DVS.iter().enumerate() {
+            if mask & 1 << i == 0 {
+                continue;
+            }
+            let mut m2 = [0; 80];
+            for ((m2, w), dm) in m2.iter_mut().zip(&w).zip(&dv.dm) {
+                *m2 = w ^ dm;
+            }
+            let (ihvin, ihvout) = recompress(dv.testt, &m2, states[dv.testt]);
+            self.ihv2 = ihvin;
+            // the other block of a colliding pair would have ended up in the same place
+            if ihvout == self.ihv {
+                self.found_collision = true;
+                if self.safe_hash {
+                    self.ihv = add(self.ihv, compress(self.ihv, &w));
+                    self.ihv = add(self.ihv, compress(self.ihv, &w));
+                }
+                break;
+            }
+        }
+    }
+}
+
+fn round(t: usize, b: u32, c: u32, d: u32) -> u32 {
+    match t {
+        0..=19  => (d ^ (b & (c ^ d))).wrapping_add(0x5A827999),
+        20..=39 => (b ^ c ^ d).wrapping_add(0x6ED9EBA1),
+        40..=59 => (b & c).wrapping_add(d & (b ^ c)).wrapping_add(0x8F1BBCDC),
+        _       => (b ^ c ^ d).wrapping_add(0xCA62C1D6)
+    }
+}
+
+fn step(t: usize, [a, b, c, d, e]: [u32; 5], w: u32) -> [u32; 5] {
+    let next = a.rotate_left(5).wrapping_add(round(t, b, c, d)).wrapping_add(e).wrapping_add(w);
+    [next, a, b.rotate_left(30), c, d]
+}
+
+fn step_back(t: usize, [next, a, b, c, d]: [u32; 5], w: u32) -> [u32; 5] {
+    let b = b.rotate_right(30);
+    let e = next.wrapping_sub(a.rotate_left(5).wrapping_add(round(t, b, c, d)).wrapping_add(w));
+    [a, b, c, d, e]
+}
+
+fn add(x: [u32; 5], y: [u32; 5]) -> [u32; 5] {
+    let mut z = x;
+    for (z, y) in z.iter_mut().zip(&y) {
+        *z = z.wrapping_add(*y);
+    }
+    z
+}
+
+fn compress(ihv: [u32; 5], w: &[u32; 80]) -> [u32; 5] {
+    (0..80).fold(ihv, |state, t| step(t, state, w[t]))
+}
+
+// runs the steps around the state before step t both ways, giving the chaining value
+// they start from and the one they end up at
+fn recompress(t: usize, w: &[u32; 80], state: [u32; 5]) -> ([u32; 5], [u32; 5]) {
+    let ihvin = (0..t).rev().fold(state, |state, i| step_back(i, state, w[i]));
+    let end = (t..80).fold(state, |state, i| step(i, state, w[i]));
+    (ihvin, add(ihvin, end))
+}
@@ -0,0 +1,66 @@
+use std::{io, fmt};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Stage {
+    Open,
+    FileHeader,
+    EntryHeader,
+    ResolveBase,
+    Inflate,
+    Delta,
+    Hash,
+    Trailer,
+    Index
+}
+
+impl Stage {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Stage::Open        => "open",
+            Stage::FileHeader  => "file header",
+            Stage::EntryHeader => "entry header",
+            Stage::ResolveBase => "delta base",
+            Stage::Inflate     => "inflate",
+            Stage::Delta       => "delta",
+            Stage::Hash        => "hash",
+            Stage::Trailer     => "trailer",
+            Stage::Index       => "index"
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub stage: Stage,
+    pub entry: Option<u32>,
+    pub offset: Option<u64>,
+    pub cause: io::Error
+}
+
+impl Error {
+    pub fn new(stage: Stage, cause: io::Error) -> Error {
+        Error { stage, entry: None, offset: None, cause }
+    }
+    pub fn at(stage: Stage, entry: u32, offset: u64, cause: io::Error) -> Error {
+        Error { stage, entry: Some(entry), offset: Some(offset), cause }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} failed", self.stage)?;
+        match (self.entry, self.offset) {
+            (Some(entry), Some(offset)) => write!(f, " at entry {}, offset {}", entry, offset)?,
+            (Some(entry), None)         => write!(f, " at entry {}", entry)?,
+            (None, Some(offset))        => write!(f, " at offset {}", offset)?,
+            (None, None)                => {}
+        }
+        write!(f, ": {}", self.cause)
+    }
+}
@@ -2,6 +2,7 @@ use std::collections::BTreeMap;
 use std::collections::btree_map;
 use std::ops::Deref;
 use std::cell::Cell;
+use std::io;
 
 #[derive(Debug)]
 pub struct PackIndex {
@@ -14,6 +15,7 @@ pub struct PackEntry {
     pub offset: u64,
     pub object: git::ObjectId,
     pub header_len: u8, // length of header
+    pub crc32: u32, // of the raw entry, header included
     pub kind: git::ObjectKind,
     pub base_index: Option<usize>, // index into PackIndex::by_offset
     pub stats: PackStats
@@ -30,6 +32,12 @@ pub struct PackStats {
     pub referenced_indirect: Counter
 }
 
+pub type Counter = Cell<u64>;
+
+fn increment(counter: &Counter) {
+    counter.set(counter.get() + 1)
+}
+
 impl PackIndex {
     pub fn with_capacity(capacity: usize) -> PackIndex {
         PackIndex {
@@ -37,21 +45,26 @@ impl PackIndex {
             by_object: BTreeMap::new()
         }
     }
-    pub fn push(&mut self, entry: PackEntry) -> &PackEntry {
-        let last_offset = self.by_offset.last().map(|e| e.offset);
-        assert!(last_offset < Some(entry.offset));
+    pub fn push(&mut self, entry: PackEntry) -> io::Result<&PackEntry> {
+        if let Some(last) = self.by_offset.last() {
+            if last.offset >= entry.offset {
+                let msg = format!("entry at offset {} doesn't follow the entry at offset {}", entry.offset, last.offset);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+            }
+        }
 
         let idx = self.by_offset.len();
         match self.by_object.entry(entry.object) {
             btree_map::Entry::Vacant(e) => {
-                e.insert(self.by_offset.len());
+                e.insert(idx);
             }
             btree_map::Entry::Occupied(e) => {
-                panic!("trying to push {:?}, colliding with existing {:?}", entry, e.get())
+                let msg = format!("duplicate object {} at offsets {} and {}", entry.object, self.by_offset[*e.get()].offset, entry.offset);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
             }
         }
         self.by_offset.push(entry);
-        &self.by_offset[idx]
+        Ok(&self.by_offset[idx])
     }
     fn find_by_offset(&self, offset: u64) -> Option<usize> {
         self.by_offset.binary_search_by_key(&offset, |e| e.offset).ok()
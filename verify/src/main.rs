@@ -1,30 +1,149 @@
-use std::{mem, io, fs};
+use std::{env, mem, io, fs, process};
+use std::ffi::OsString;
 use std::io::{Read, BufRead, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 use flate2::bufread::ZlibDecoder;
 use index::{PackIndex, PackEntry, PackStats};
+use error::{Error, Stage};
 
 mod index;
+mod error;
 
-const PACK_PATH: &'static str = "/home/src/android-base/.git/objects/pack/pack-c545e08123f0f1cee2b7e40c4ace577f73213498.pack";
+const USAGE: &str = "usage: verify [--quiet | --progress] [--object-format=<sha1|sha256>] <pack>...";
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Mode {
+    Quiet,
+    Normal,
+    Progress
+}
 
 fn main() {
-    let mut file = fs::File::open(PACK_PATH).map(io::BufReader::new).unwrap();
-    let file_header = gulp::from_reader(&mut file, git_pack::FileHeaderParser::default).unwrap();
+    let (mode, hash, paths) = match parse_args(env::args_os().skip(1)) {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("verify: {}\n{}", msg, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let mut failed = 0;
+    for path in &paths {
+        match verify(path, mode, hash) {
+            Ok(count) => if mode != Mode::Quiet {
+                println!("{}: ok ({} entries)", path.display(), count);
+            },
+            Err(e) => {
+                failed += 1;
+                eprintln!("{}: {}", path.display(), e);
+            }
+        }
+    }
+
+    if failed != 0 {
+        process::exit(1);
+    }
+}
+
+fn parse_args<I: Iterator<Item=OsString>>(args: I) -> Result<(Mode, git::HashKind, Vec<PathBuf>), String> {
+    let mut mode = Mode::Normal;
+    let mut hash = git::HashKind::Sha1;
+    let mut paths = Vec::new();
+    let mut flags = true;
+    for arg in args {
+        if flags {
+            match arg.to_str() {
+                Some("--") => { flags = false; continue }
+                Some("-h") | Some("--help") => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                }
+                Some("-q") | Some("--quiet")    => { mode = Mode::Quiet; continue }
+                Some("-p") | Some("--progress") => { mode = Mode::Progress; continue }
+                Some(flag) if flag.starts_with("--object-format=") => {
+                    let name = &flag["--object-format=".len()..];
+                    hash = git::HashKind::from_name(name.as_bytes()).ok_or_else(|| format!("unknown object format '{}'", name))?;
+                    continue
+                }
+                Some(flag) if flag.starts_with('-') => return Err(format!("unknown option '{}'", flag)),
+                _ => {}
+            }
+        }
+        paths.push(PathBuf::from(arg));
+    }
+    if paths.is_empty() {
+        return Err("no packs given".to_owned());
+    }
+    Ok((mode, hash, paths))
+}
+
+fn verify(path: &Path, mode: Mode, hash: git::HashKind) -> Result<u32, Error> {
+    let mut file = fs::File::open(path).map(io::BufReader::new).map(|f| git_pack::ChecksumReader::with_hash(hash, f))
+        .map_err(|e| Error::new(Stage::Open, e))?;
+    let file_header = gulp::from_reader(&mut file, git_pack::FileHeaderParser::default)
+        .map_err(|e| Error::new(Stage::FileHeader, e.into()))?;
+    let offset = file.offset();
     let mut reader = ObjectReader {
-        reader: OffsetReader::new(file),
+        reader: git_pack::EntryReader::with_hash(hash, file, offset),
+        hash,
         base: Vec::new(),
         output: Vec::new(),
         layers: Vec::new(),
         index: PackIndex::with_capacity(file_header.count as usize)
     };
-    for n in 0..file_header.count {
-        print!("\r{} of {} ({}%)", n, file_header.count, n*100/file_header.count);
-        let (_entry, _output) = reader.next().unwrap();
+
+    let count = file_header.count;
+    let mut percent = None;
+    for n in 0..count {
+        if mode == Mode::Progress && percent != Some(n as u64 * 100 / count as u64) {
+            percent = Some(n as u64 * 100 / count as u64);
+            eprint!("\r{}: {} of {} ({}%)", path.display(), n, count, percent.unwrap());
+        }
+        let result = reader.next().map(|_| ());
+        if let Err(e) = result {
+            if mode == Mode::Progress {
+                eprintln!();
+            }
+            return Err(e);
+        }
+    }
+    if mode == Mode::Progress {
+        eprintln!("\r{}: {} of {} (100%)", path.display(), count, count);
+    }
+
+    let index = reader.index;
+    let reader = reader.reader.into_inner();
+    let offset = reader.offset();
+    let checksum = git_pack::verify_trailer(reader)
+        .map_err(|e| Error { stage: Stage::Trailer, entry: None, offset: Some(offset), cause: e.into() })?;
+
+    let index_path = path.with_extension("idx");
+    if index_path.exists() {
+        verify_index(&index_path, &index, checksum).map_err(|e| Error::new(Stage::Index, e))?;
     }
+
+    Ok(count)
+}
+
+fn verify_index(path: &Path, index: &PackIndex, checksum: git::ObjectId) -> io::Result<()> {
+    let file = fs::File::open(path).map(io::BufReader::new)?;
+    let stored = gulp::from_reader(file, || git_pack::IndexParser::new(checksum.hash()))?;
+    let entries = index.iter()
+        .map(|e| git_pack::IndexEntry { id: e.object, offset: e.offset, crc32: e.crc32 })
+        .collect();
+    let computed = git_pack::Index::from_entries(entries, checksum).map_err(|e| invalid_data(e.to_string()))?;
+    if stored.pack_checksum() != checksum {
+        return Err(invalid_data("index belongs to a different pack"));
+    }
+    if stored.entries() != computed.entries() {
+        return Err(invalid_data("index entries don't match pack"));
+    }
+    Ok(())
 }
 
 struct ObjectReader<R: BufRead + Seek> {
-    reader: OffsetReader<R>,
+    reader: git_pack::EntryReader<R>,
+    hash: git::HashKind,
     base: Vec<u8>,
     output: Vec<u8>,
     layers: Vec<u64>,
@@ -32,13 +151,14 @@ struct ObjectReader<R: BufRead + Seek> {
 }
 
 impl<R: BufRead + Seek> ObjectReader<R> {
-    fn next(&mut self) -> io::Result<(&PackEntry, &[u8])> {
+    fn next(&mut self) -> Result<(&PackEntry, &[u8]), Error> {
+        let entry = self.index.len() as u32;
+
         // read our entry
-        let (offset, header, body_offset) = {
-            (self.reader.offset()?,
-             gulp::from_reader(&mut self.reader, git_pack::EntryHeaderParser::default)?,
-             self.reader.offset()?)
-        };
+        let offset = self.reader.offset();
+        let fail = move |stage| move |e| Error::at(stage, entry, offset, e);
+        let header = self.reader.next_header().map_err(|e| fail(Stage::EntryHeader)(e.into()))?;
+        let body_offset = self.reader.offset();
 
         self.layers.clear();
         let (kind, base_index) = match header {
@@ -46,92 +166,66 @@ impl<R: BufRead + Seek> ObjectReader<R> {
             git_pack::EntryHeader::Delta(delta_header) => {
                 self.layers.push(body_offset);
                 let base = match delta_header.base {
-                    git_pack::DeltaBase::Offset(off) => git_pack::DeltaBase::Offset(offset - off),
+                    git_pack::DeltaBase::Offset(off) => match offset.checked_sub(off) {
+                        Some(off) => git_pack::DeltaBase::Offset(off),
+                        None => return Err(fail(Stage::ResolveBase)(invalid_data("delta base offset precedes start of pack")))
+                    },
                     base => base
                 };
                 let base = match self.index.resolve_base(&mut self.layers, base) {
                     Some(b) => b,
-                    None => {
-                        println!("known entries: {:?}", &self.index[..]);
-                        panic!("can't find base: {:?}", delta_header);
-                    }
+                    None => return Err(fail(Stage::ResolveBase)(invalid_data(format!("can't find base: {:?}", base))))
                 };
                 let root_offset = base.root_entry.offset + base.root_entry.header_len as u64;
-                self.reader.seek(SeekFrom::Start(root_offset))?;
+                self.reader.seek(SeekFrom::Start(root_offset)).map_err(fail(Stage::Inflate))?;
                 (base.root_entry.kind, Some(base.base_index))
             }
         };
 
-        self.output.clear();
-        ZlibDecoder::new(&mut self.reader).read_to_end(&mut self.output)?;
+        self.base.clear();
+        ZlibDecoder::new(&mut self.reader).read_to_end(&mut self.base).map_err(fail(Stage::Inflate))?;
 
+        // collapse the chain into one delta against the root, so the root is only walked once
+        let mut composer: Option<git_delta::Composer> = None;
         for layer_offset in self.layers.drain(..).rev() {
-            mem::swap(&mut self.base, &mut self.output);
-            let base = io::Cursor::new(&self.base);
-
-            self.reader.seek(SeekFrom::Start(layer_offset))?;
+            self.reader.seek(SeekFrom::Start(layer_offset)).map_err(fail(Stage::Delta))?;
             let delta = io::BufReader::new(ZlibDecoder::new(&mut self.reader));
-            self.output.clear();
-            git_delta::Reader::new(base, delta)?.read_to_end(&mut self.output)?;
+            match composer {
+                Some(ref mut composer) => composer.push(delta),
+                None => git_delta::Composer::new(delta).map(|c| composer = Some(c))
+            }.map_err(fail(Stage::Delta))?;
+        }
+
+        self.output.clear();
+        match composer {
+            None => mem::swap(&mut self.base, &mut self.output),
+            Some(composer) => {
+                let delta = composer.encode().map_err(fail(Stage::Delta))?;
+                let output = &mut self.output;
+                git_delta::ReaderAt::new_strict(&self.base[..], self.base.len() as u64, &delta[..])
+                    .and_then(|mut r| r.read_to_end(output))
+                    .map_err(fail(Stage::Delta))?;
+            }
         }
 
         let object = {
             let size = self.output.len() as u64;
-            let mut hasher = git::ObjectHasher::new(git::ObjectHeader { kind, size });
+            let mut hasher = git::ObjectHasher::with_hash(self.hash, git::ObjectHeader { kind, size });
             hasher.update(&self.output);
-            hasher.digest()
+            hasher.digest().map_err(|e| fail(Stage::Hash)(invalid_data(e.to_string())))?
         };
 
         let entry = self.index.push(PackEntry {
             offset, object, kind, base_index,
             header_len: (body_offset - offset) as u8,
+            crc32: self.reader.span().crc32,
             stats: PackStats::default()
-        });
+        }).map_err(fail(Stage::Index))?;
 
         Ok((entry, &self.output))
     }
 }
 
-struct OffsetReader<R: BufRead + Seek> {
-    reader: R,
-    offset: Option<u64>
-}
-
-impl<R: BufRead + Seek> OffsetReader<R> {
-    fn new(reader: R) -> OffsetReader<R> {
-        OffsetReader { reader, offset: None }
-    }
-    fn offset(&mut self) -> io::Result<u64> {
-        self.seek(SeekFrom::Current(0))
-    }
-}
-
-impl<R: BufRead + Seek> Read for OffsetReader<R> {
-    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
-        let len = self.reader.read(buffer)?;
-        self.offset = self.offset.and_then(|off| off.checked_add(len as u64));
-        Ok(len)
-    }
-}
-
-impl<R: BufRead + Seek> BufRead for OffsetReader<R> {
-    fn fill_buf(&mut self) -> io::Result<&[u8]> {
-        self.reader.fill_buf()
-    }
-    fn consume(&mut self, amt: usize) {
-        self.reader.consume(amt);
-        self.offset = self.offset.and_then(|off| off.checked_add(amt as u64));
-    }
-}
-
-impl<R: BufRead + Seek> Seek for OffsetReader<R> {
-    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
-        Ok(if let (SeekFrom::Current(0), Some(offset)) = (pos, self.offset) {
-            offset
-        } else {
-            let offset = self.reader.seek(pos)?;
-            self.offset = Some(offset);
-            offset
-        })
-    }
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
 }